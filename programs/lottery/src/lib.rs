@@ -1,11 +1,56 @@
 use anchor_lang::prelude::*;
 use anchor_spl::associated_token::{ self, Create, AssociatedToken };
-use anchor_spl::token::{ self, Transfer, Mint, Token, TokenAccount };
+use anchor_spl::token::{ self, Burn, MintTo, SetAuthority, Transfer, Mint, Token, TokenAccount };
+use anchor_spl::token::spl_token::instruction::AuthorityType;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::solana_program::sysvar::slot_hashes;
+use anchor_lang::solana_program::system_instruction::MAX_PERMITTED_DATA_LENGTH;
+use anchor_lang::system_program;
+use std::cell::{ Ref, RefMut };
 use std::collections::HashMap;
-mod randomness_tools;
-use randomness_tools::get_sha256_hashed_random;
+use std::ops::Range;
+use sha2::{ Digest, Sha256 };
+pub mod randomness_tools;
+use randomness_tools::{ fold_entropy, mix_entropy, RandomnessStream };
+pub mod merkle_tools;
+use merkle_tools::{ allowlist_leaf, verify_proof };
 declare_id!("E5Tmweyj2XLDn1L746PPdt7dAbG397qvTj8wYBqEaBSX");
 
+// longest time a round accepts ticket purchases, slots up to MAX_SLOT_DRIFT_PERCENT slower than
+// MS_PER_SLOT reach the target slot at most 18 hours after `end`, inside REVEAL_WINDOW
+pub const MAX_ROUND_DURATION: i64 = 60 * 60 * 24 * 3; // 3 days
+// how much slower than MS_PER_SLOT the slots of a round may run on average, see MAX_ROUND_DURATION
+pub const MAX_SLOT_DRIFT_PERCENT: i64 = 25;
+// how long the operator has after `end` to reveal the committed secret
+pub const REVEAL_WINDOW: i64 = 60 * 60 * 24; // 24 hours
+// slot time used to place the slot-hash target after `end`
+pub const MS_PER_SLOT: i64 = 400;
+// slots between the estimated end of the round and the committed slot-hash target
+pub const SLOT_HASH_DELAY: u64 = 150;
+// bumped whenever the draw or the ticket snapshot layout changes, see DrawRecord
+pub const DRAW_ALGORITHM_VERSION: u8 = 8;
+// sold tickets one draw transaction goes through, or tickets it draws in a raffle, see continue_draw
+pub const DRAW_BATCH: u32 = 250;
+// largest account the program can create itself, used for the lottery account
+pub const LOTTERY_SPACE: usize = 10240;
+// largest account the system program creates, the client-allocated ticket pool can't be larger
+pub const MAX_TICKET_POOL_SPACE: usize = MAX_PERMITTED_DATA_LENGTH as usize;
+// highest ticket price, what a ticket paid is kept in 6 bytes of the ticket pool
+pub const MAX_TICKET_PRICE: u64 = (1 << 48) - 1;
+// most numbers a pick-numbers ticket holds
+pub const MAX_PICK_COUNT: u8 = 10;
+// most bundles in a lottery price schedule
+pub const MAX_PRICE_TIERS: usize = 8;
+// most tickets in one bundle, price_of keeps a price per ticket of the largest bundle on the heap
+pub const MAX_BUNDLE_TICKETS: u32 = 100;
+// most tickets one purchase buys, price_of and the ticket assignment run once per ticket, so this
+// keeps a purchase at MAX_PURCHASE_TICKETS * (MAX_PRICE_TIERS + 1) pricing steps
+pub const MAX_PURCHASE_TICKETS: u64 = MAX_BUNDLE_TICKETS as u64;
+// longest gift message of buy_tickets_for, in bytes
+pub const MAX_GIFT_MEMO_LEN: usize = 200;
+// latest rounds of an operator kept in AppStats::current_round_list
+pub const MAX_LISTED_ROUNDS: usize = 250;
+
 /*
 collect fees when user buys ticket
 unresolved case
@@ -61,353 +106,1128 @@ pub mod lottery {
         app_stats.current_round = 0;
         app_stats.current_round_key = Pubkey::default();
         app_stats.current_round_list = Vec::new();
+        // no oracle until the owner configures one, rounds use commit-reveal
+        app_stats.oracle_program = Pubkey::default();
+        app_stats.royalty_percent = 0;
+        app_stats.referral_percent = 0;
         //app_stats.mint = ctx.accounts.mint.key();
         app_stats.bump = bump;
         Ok(())
     }
 
-    pub fn update_app_stats(ctx: Context<UpdateAppStats>, fee_percent: u8) -> Result<()> {
+    /**
+     * Update the app stats account
+     * @param ctx is the context of the program
+     * @param fee_percent is the fee percentage to be charged
+     * @param oracle_program is the VRF oracle used by new rounds, default pubkey to use commit-reveal
+     * @param royalty_percent is the share of ticket resales paid to the fee account
+     * @param referral_percent is the share of ticket fees paid to the buyer's referrer
+     * @return the result of the operation
+     */
+    pub fn update_app_stats(
+        ctx: Context<UpdateAppStats>,
+        fee_percent: u8,
+        oracle_program: Pubkey,
+        royalty_percent: u8,
+        referral_percent: u8
+    ) -> Result<()> {
+        if royalty_percent > 100 || referral_percent > 100 {
+            return err!(ErrCode::InvalidArgus);
+        }
         let app_stats = &mut ctx.accounts.app_stats;
         app_stats.fee_account = ctx.accounts.fee_account.key();
         app_stats.fee_percent = fee_percent;
+        app_stats.oracle_program = oracle_program;
+        app_stats.royalty_percent = royalty_percent;
+        app_stats.referral_percent = referral_percent;
         Ok(())
     }
 
+    /**
+     * Create a lottery round, pick-numbers rounds pass rollover_token, a prize token account of the
+     * creator receiving the pots of tiers nobody won and the remainders of the tier splits
+     * @param ctx is the context of the program
+     * @param ticket_price is the price of one ticket in prize tokens, at most MAX_TICKET_PRICE
+     * @param ticket_amount is the number of tickets, at most TicketPool::max_capacity(pick_count), ticket_pool must be allocated with TicketPool::space(ticket_amount, pick_count)
+     * @param round_duration is how many seconds the round sells tickets, at most MAX_ROUND_DURATION, slot-hash and commit-reveal rounds close earlier when slots run faster than MS_PER_SLOT
+     * @param max_tickets_per_buyer is the most tickets one wallet can hold, 0 for no limit
+     * @param prize_bump is the bump of the prize account
+     * @param proceeds_bump is the bump of the proceeds account
+     * @param secret_commitment is sha256(secret) for commit-reveal rounds, zero for slot-hash rounds
+     * @param nft_tickets lets buyers mint their tickets as SPL tokens with mint_ticket, not in capped, allowlisted or gated rounds
+     * @param mode is a raffle over sold tickets or a lotto over numbers picked by the buyers
+     * @param pick_count is how many numbers a pick-numbers ticket holds, zero for raffles
+     * @param pick_range is the highest number a pick-numbers ticket can hold, zero for raffles
+     * @param prize_tiers are the pick-numbers payouts by matched numbers, their percents add up to 100, empty for raffles
+     * @param price_tiers are optional bundles of several tickets sold at a lower price, single tickets cost ticket_price
+     * @param allowlist_root is the merkle root of the wallets allowed to buy, see merkle_tools, zero for a public round
+     * @param gate_mint is the token buyers must hold to take part, default pubkey for no gate
+     * @param gate_min_balance is the least balance of gate_mint a buyer must hold
+     * @param cancel_penalty_percent is the share of a cancelled ticket's refund paid to the fee account
+     * @return the result of the operation
+     */
+    #[allow(clippy::too_many_arguments)]
     pub fn create_lottery(
         ctx: Context<CreateLottery>,
         ticket_price: u64,
-        ticket_amount: u8,
+        ticket_amount: u32,
+        round_duration: i64,
+        max_tickets_per_buyer: u32,
         prize_bump: u8,
-        proceeds_bump: u8
+        proceeds_bump: u8,
+        secret_commitment: [u8; 32],
+        nft_tickets: bool,
+        mode: LotteryMode,
+        pick_count: u8,
+        pick_range: u8,
+        prize_tiers: Vec<PrizeTier>,
+        price_tiers: Vec<PriceTier>,
+        allowlist_root: [u8; 32],
+        gate_mint: Pubkey,
+        gate_min_balance: u64,
+        cancel_penalty_percent: u8
     ) -> Result<()> {
         // Validations, check if the caller is admin, if ticket price is not zero, if ticket amount is not zero
         if ctx.accounts.app_stats.owner != ctx.accounts.signer.key() {
             return err!(ErrCode::CallerIsNotAdmin);
         }
-        if ticket_price == 0{
+        if ticket_price == 0 || ticket_price > MAX_TICKET_PRICE {
             return err!(ErrCode::InvalidTicketPrice);
         }
-        if  ticket_amount <= 0 {
+        if round_duration <= 0 || round_duration > MAX_ROUND_DURATION {
+            return err!(ErrCode::InvalidRoundDuration);
+        }
+        if cancel_penalty_percent > 100 {
+            return err!(ErrCode::InvalidArgus);
+        }
+        // ticket tokens move with plain SPL transfers, which can't check the cap, the allowlist or the gate
+        if nft_tickets && (max_tickets_per_buyer != 0 || allowlist_root != [0u8; 32] || gate_mint != Pubkey::default()) {
+            return err!(ErrCode::RestrictedNftTickets);
+        }
+        match mode {
+            LotteryMode::Raffle => if pick_count != 0 || pick_range != 0 || !prize_tiers.is_empty() {
+                return err!(ErrCode::InvalidPicks);
+            },
+            LotteryMode::PickNumbers => if pick_count == 0 || pick_count > MAX_PICK_COUNT || pick_count > pick_range {
+                return err!(ErrCode::InvalidPicks);
+            },
+        }
+        let rollover_account = ctx.accounts.rollover_token.as_ref().map_or(Pubkey::default(), |token| token.key());
+        // highest tier first, a ticket is paid by the first tier it reaches
+        let mut prize_tiers = prize_tiers;
+        prize_tiers.sort_by_key(|tier| std::cmp::Reverse(tier.matches));
+        if mode == LotteryMode::PickNumbers {
+            let percent_total: u32 = prize_tiers.iter().map(|tier| tier.percent as u32).sum();
+            if prize_tiers.is_empty()
+                || percent_total != 100
+                || prize_tiers.iter().any(|tier| tier.matches == 0 || tier.matches > pick_count)
+                || prize_tiers.windows(2).any(|pair| pair[0].matches == pair[1].matches)
+                || rollover_account == Pubkey::default() {
+                return err!(ErrCode::InvalidPrizeTiers);
+            }
+        }
+        // a bundle must hold several tickets, no more than the round sells,
+        // and cost less than buying them one by one
+        if price_tiers.len() > MAX_PRICE_TIERS
+            || price_tiers.iter().any(|tier| tier.tickets < 2
+                || tier.tickets > MAX_BUNDLE_TICKETS
+                || tier.tickets > ticket_amount
+                || tier.price == 0
+                || ticket_price.checked_mul(tier.tickets as u64).map_or(true, |singles| tier.price >= singles)) {
+            return err!(ErrCode::InvalidPriceTiers);
+        }
+        // the round size is bounded by the largest ticket pool the client can allocate,
+        // 1,035,625 raffle tickets or 521,028 tickets of 10 picked numbers
+        if ticket_amount == 0 || ticket_amount > TicketPool::max_capacity(pick_count) {
             return err!(ErrCode::InvalidTicketAmount);
-        }      
+        }
+        if ctx.accounts.ticket_pool.to_account_info().data_len() < TicketPool::space(ticket_amount, pick_count) {
+            return err!(ErrCode::InvalidTicketPool);
+        }
+        // the oracle when one is configured, otherwise the operator commits to sha256(secret)
+        // and reveals the secret after `end` to be mixed with the hash of a slot committed now,
+        // without a commitment the draw uses that slot hash alone
+        let oracle_program = ctx.accounts.app_stats.oracle_program;
+        let randomness_source = if oracle_program != Pubkey::default() {
+            RandomnessSource::Oracle
+        } else if secret_commitment != [0u8; 32] {
+            RandomnessSource::CommitReveal
+        } else {
+            RandomnessSource::SlotHash
+        };
 
         let lottery = &mut ctx.accounts.lottery;
         lottery.ticket_price = ticket_price;
         lottery.ticket_amount = ticket_amount;
-        lottery.left_tickets = (1..=lottery.ticket_amount).collect();
-        lottery.start = ctx.accounts.clock.unix_timestamp as i64;
-        lottery.end = ctx.accounts.clock.unix_timestamp as i64 + 60 * 60 * 24; // 24 hours
+        lottery.tickets_sold = 0;
+        lottery.ticket_pool = ctx.accounts.ticket_pool.key();
+        lottery.schedule(&ctx.accounts.clock, round_duration);
+        lottery.randomness_source = randomness_source;
+        lottery.secret_commitment = secret_commitment;
+        lottery.saved_slot_hash = [0u8; 32];
+        // snapshot the oracle so it cannot be swapped while the round is running
+        lottery.oracle_program = oracle_program;
+        lottery.randomness_account = Pubkey::default();
+        lottery.entropy_accumulator = [0u8; 32];
         lottery.creator = ctx.accounts.signer.key();
         lottery.prize_token = ctx.accounts.mint.key();
         lottery.prize_bump = prize_bump;
         lottery.proceeds_bump = proceeds_bump;
-        lottery.max_tickets_per_buyer = 5;
+        lottery.max_tickets_per_buyer = max_tickets_per_buyer;
+        lottery.nft_tickets = nft_tickets;
+        lottery.mode = mode;
+        lottery.pick_count = pick_count;
+        lottery.pick_range = pick_range;
+        lottery.prize_tiers = prize_tiers;
+        lottery.rollover_account = rollover_account;
+        lottery.rollover_swept = false;
+        lottery.price_tiers = price_tiers;
+        lottery.allowlist_root = allowlist_root;
+        lottery.gate_mint = gate_mint;
+        lottery.gate_min_balance = gate_min_balance;
+        lottery.cancel_penalty_percent = cancel_penalty_percent;
         lottery.status = LotteryStatus::Running;
 
+        // zeroed slots stand for their own ticket number, the pool needs no filling
+        let mut ticket_pool = ctx.accounts.ticket_pool.load_init()?;
+        ticket_pool.lottery = lottery.key();
+        ticket_pool.capacity = ticket_amount;
+        ticket_pool.remaining = ticket_amount;
+        ticket_pool.pick_count = pick_count as u32;
+
         let app_stats = &mut ctx.accounts.app_stats;
         app_stats.current_round += 1;
         app_stats.current_round_key = lottery.key();
+        if app_stats.current_round_list.len() == MAX_LISTED_ROUNDS {
+            app_stats.current_round_list.remove(0);
+        }
         app_stats.current_round_list.push(lottery.key());
         
         Ok(())
     }
 
-    pub fn buy_tickets(ctx: Context<BuyTickets>, ticket_amount: u64) -> Result<()> {
-        let lottery: &mut Account<'_, Lottery> = &mut ctx.accounts.lottery;
-        let ticket_price:u64 = lottery.ticket_price;
-        
-        let now = ctx.accounts.clock.unix_timestamp as i64;
-        let end_ts = lottery.end;
+    /**
+     * Buy tickets for the current round, passing the optional referrer_stats and referral_vault
+     * of a referrer pays it its share of the fee
+     * @param ctx is the context of the program
+     * @param ticket_amount is the number of tickets to buy, at most MAX_PURCHASE_TICKETS
+     * @param client_seed is optional buyer entropy folded into the draw seed
+     * @param picks are pick_count distinct numbers in 1..=pick_range per ticket, one ticket after the other, empty for raffles
+     * @param allowlist_proof is the merkle proof of the signer's allowlist leaf, empty for public rounds
+     * @param allowance is the ticket allowance of the signer's allowlist leaf, 0 for none
+     * @return the result of the operation
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub fn buy_tickets(
+        ctx: Context<BuyTickets>,
+        ticket_amount: u64,
+        client_seed: Option<[u8; 32]>,
+        picks: Vec<u8>,
+        allowlist_proof: Vec<[u8; 32]>,
+        allowance: u32
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        let order = TicketOrder {
+            owner: accounts.sale.signer.key(),
+            ticket_amount,
+            client_seed,
+            picks,
+            allowlist_proof,
+            allowance,
+        };
+        sell_tickets(&mut accounts.sale, &mut accounts.receipt, ctx.bumps.receipt, &accounts.system_program, order)?;
+        Ok(())
+    }
 
-        // check duration sanity
-        if now > end_ts {
-            return err!(ErrCode::RoundEnded);
+    /**
+     * Buy tickets paid by the signer for another wallet, which holds them as if it bought them
+     * @param ctx is the context of the program
+     * @param beneficiary is the wallet receiving the tickets, the per-wallet cap applies to it
+     * @param ticket_amount is the number of tickets to buy, at most MAX_PURCHASE_TICKETS
+     * @param client_seed is optional buyer entropy folded into the draw seed
+     * @param picks are pick_count distinct numbers in 1..=pick_range per ticket, one ticket after the other, empty for raffles
+     * @param memo is an optional gift message of at most MAX_GIFT_MEMO_LEN bytes, emitted with TicketsGifted
+     * @param allowlist_proof is the merkle proof of the beneficiary's allowlist leaf, empty for public rounds
+     * @param allowance is the ticket allowance of the beneficiary's allowlist leaf, 0 for none
+     * @return the result of the operation
+     */
+    #[allow(clippy::too_many_arguments)]
+    pub fn buy_tickets_for(
+        ctx: Context<BuyTicketsFor>,
+        beneficiary: Pubkey,
+        ticket_amount: u64,
+        client_seed: Option<[u8; 32]>,
+        picks: Vec<u8>,
+        memo: Option<String>,
+        allowlist_proof: Vec<[u8; 32]>,
+        allowance: u32
+    ) -> Result<()> {
+        let accounts = &mut *ctx.accounts;
+        if beneficiary == accounts.sale.signer.key() {
+            return err!(ErrCode::InvalidArgus);
+        }
+        if memo.as_ref().is_some_and(|memo| memo.len() > MAX_GIFT_MEMO_LEN) {
+            return err!(ErrCode::MemoTooLong);
+        }
+
+        let order = TicketOrder {
+            owner: beneficiary,
+            ticket_amount,
+            client_seed,
+            picks,
+            allowlist_proof,
+            allowance,
+        };
+        let tickets = sell_tickets(&mut accounts.sale, &mut accounts.receipt, ctx.bumps.receipt, &accounts.system_program, order)?;
+
+        emit!(TicketsGifted {
+            lottery: accounts.sale.lottery.key(),
+            payer: accounts.sale.signer.key(),
+            beneficiary,
+            tickets,
+            memo,
+        });
+        Ok(())
+    }
+
+    /**
+     * Return tickets to the pool before the sales close, the signer gets back what the tickets
+     * paid into the prize account less the lottery's cancellation penalty, which goes to the fee account
+     * @param ctx is the context of the program
+     * @param ticket_numbers are the tickets to cancel, they must be on the signer's receipt
+     * @param ticket_slots are the pool slots holding ticket_numbers, one per ticket, see TicketPool::find_slot
+     * @return the result of the operation
+     */
+    pub fn cancel_tickets(ctx: Context<CancelTickets>, ticket_numbers: Vec<u32>, ticket_slots: Vec<u32>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        if lottery.status != LotteryStatus::Running {
+            return err!(ErrCode::LotteryNotRunning);
+        }
+        // the same window as buy_tickets, nobody can cancel once the draw can be predicted
+        lottery.check_sales_open(&ctx.accounts.clock)?;
+        if ticket_numbers.is_empty() || ticket_slots.len() != ticket_numbers.len() {
+            return err!(ErrCode::InvalidArgus);
         }
 
-        // check available tickets
-        if ticket_amount == 0 || ticket_amount > (lottery.left_tickets.len() as u64) {
+        // every ticket is refunded what its purchase paid for it into the prize account, a bundle
+        // ticket paid less than ticket_price and a fee was kept, so refunding ticket_price would
+        // pay out of the other buyers' prize
+        let receipt = &mut ctx.accounts.receipt;
+        let (mut ticket_pool, mut pool_tickets) = TicketPool::load_mut(&ctx.accounts.ticket_pool)?;
+        // returning a ticket moves the first sold ticket into its slot, going from the lowest slot
+        // up keeps the other given slots on their tickets
+        let mut cancelled: Vec<(u32, u32)> = ticket_slots.into_iter().zip(ticket_numbers).collect();
+        cancelled.sort();
+        let mut amount: u64 = 0;
+        for &(slot, ticket_number) in cancelled.iter() {
+            let index = receipt.tickets.iter()
+                .position(|&ticket| ticket == ticket_number)
+                .ok_or(ErrCode::InvalidBuyer)?;
+            receipt.tickets.swap_remove(index);
+            if slot < ticket_pool.remaining
+                || slot >= ticket_pool.capacity
+                || TicketPool::ticket_at(&pool_tickets, slot as usize) != ticket_number {
+                return err!(ErrCode::InvalidArgus);
+            }
+            amount += ticket_pool.paid_for(&pool_tickets, ticket_number);
+            ticket_pool.return_ticket(&mut pool_tickets, slot as usize);
+        }
+
+        let penalty = (amount * lottery.cancel_penalty_percent as u64) / 100;
+        lottery.tickets_sold -= cancelled.len() as u32;
+        lottery.collected -= amount;
+
+        transfer_from_prize(
+            lottery,
+            &ctx.accounts.prize,
+            &ctx.accounts.user_token.to_account_info(),
+            &ctx.accounts.token_program,
+            amount - penalty
+        )?;
+        if penalty > 0 {
+            transfer_from_prize(
+                lottery,
+                &ctx.accounts.prize,
+                &ctx.accounts.fee_token.to_account_info(),
+                &ctx.accounts.token_program,
+                penalty
+            )?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Move a ticket to another wallet while ticket sales are open
+     * @param ctx is the context of the program
+     * @param ticket_number is the ticket to move, it must be on the signer's receipt
+     * @param new_owner is the wallet receiving the ticket
+     * @param allowlist_proof is the merkle proof of the new owner's allowlist leaf, empty for public rounds
+     * @param allowance is the ticket allowance of the new owner's allowlist leaf, 0 for none
+     * @return the result of the operation
+     */
+    pub fn transfer_ticket(
+        ctx: Context<TransferTicket>,
+        ticket_number: u32,
+        new_owner: Pubkey,
+        allowlist_proof: Vec<[u8; 32]>,
+        allowance: u32
+    ) -> Result<()> {
+        let lottery = &ctx.accounts.lottery;
+        if lottery.status != LotteryStatus::Running {
+            return err!(ErrCode::LotteryNotRunning);
+        }
+        // once the draw can be predicted a holder could hand off known losers
+        lottery.check_sales_open(&ctx.accounts.clock)?;
+        if new_owner == ctx.accounts.signer.key() {
             return err!(ErrCode::InvalidArgus);
         }
 
-        //let total_amount = amount * ticket_price;
-        let fee_amount: u64 = (ticket_amount as u64 * ticket_price * (ctx.accounts.app_stats.fee_percent as u64)) / 100;
-        let real_amount:u64 = (ticket_amount as u64) * ticket_price - fee_amount;
+        let receipt = &mut ctx.accounts.receipt;
+        let index = receipt.tickets.iter()
+            .position(|&ticket| ticket == ticket_number)
+            .ok_or(ErrCode::InvalidBuyer)?;
+
+        let new_owner_receipt = &mut ctx.accounts.new_owner_receipt;
+        new_owner_receipt.open(lottery.key(), new_owner, ctx.bumps.new_owner_receipt);
+        // the receiving wallet is held to the same cap, allowlist and gate as a buyer
+        lottery.check_ticket_cap(new_owner_receipt.held_tickets(), 1)?;
+        lottery.check_entry(&new_owner, ctx.accounts.gate_token.as_deref(), &allowlist_proof, allowance, new_owner_receipt.held_tickets() + 1)?;
+        resize_receipt(
+            &new_owner_receipt.to_account_info(),
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            new_owner_receipt.held_tickets() + 1
+        )?;
+
+        receipt.tickets.remove(index);
+        new_owner_receipt.tickets.push(ticket_number);
+
+        emit!(TicketTransferred {
+            lottery: lottery.key(),
+            ticket_number,
+            from: ctx.accounts.signer.key(),
+            to: new_owner,
+        });
+        Ok(())
+    }
+
+    /**
+     * Put a ticket up for sale, the ticket leaves the seller's receipt and is held by the listing
+     * @param ctx is the context of the program
+     * @param ticket_number is the ticket to sell, it must be on the signer's receipt
+     * @param price is the asking price in prize tokens, at most MAX_TICKET_PRICE
+     * @return the result of the operation
+     */
+    pub fn list_ticket(ctx: Context<ListTicket>, ticket_number: u32, price: u64) -> Result<()> {
+        let lottery = &ctx.accounts.lottery;
+        if lottery.status != LotteryStatus::Running {
+            return err!(ErrCode::LotteryNotRunning);
+        }
+        // once the draw can be predicted sellers could dump known losers
+        lottery.check_sales_open(&ctx.accounts.clock)?;
+        if price == 0 || price > MAX_TICKET_PRICE {
+            return err!(ErrCode::InvalidTicketPrice);
+        }
+
+        let receipt = &mut ctx.accounts.receipt;
+        let index = receipt.tickets.iter()
+            .position(|&ticket| ticket == ticket_number)
+            .ok_or(ErrCode::InvalidBuyer)?;
+        receipt.tickets.remove(index);
+        receipt.listed_tickets += 1;
+
+        let listing = &mut ctx.accounts.listing;
+        listing.lottery = lottery.key();
+        listing.seller = ctx.accounts.seller.key();
+        listing.ticket_number = ticket_number;
+        listing.price = price;
+        listing.bump = ctx.bumps.listing;
+        Ok(())
+    }
+
+    /**
+     * Take a ticket off sale and give it back to the seller.
+     * Once the winners are drawn the listing is void and anyone can return the ticket,
+     * the signer pays if the receipt has to grow to take it back.
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn cancel_listing(ctx: Context<CancelListing>) -> Result<()> {
+        if ctx.accounts.lottery.status == LotteryStatus::Running && ctx.accounts.signer.key() != ctx.accounts.seller.key() {
+            return err!(ErrCode::InvalidSeller);
+        }
 
-        ///transfer fee to fee account
-        // let cpi_accounts = Transfer {
-        //     from: ctx.accounts.creator_token.to_account_info(),
-        //     to: ctx.accounts.fee_account.to_account_info(),
-        //     authority: ctx.accounts.signer.to_account_info(),
-        // };
-        // let cpi_program = ctx.accounts.token_program.to_account_info();
-        // let cpi_ctx: CpiContext<Transfer> = CpiContext::new(cpi_program, cpi_accounts);
-        // token::transfer(cpi_ctx, fee_amount)?;
+        // held_tickets() counts the listed ticket, so this only grows receipts sized before listings were counted
+        let receipt = &mut ctx.accounts.receipt;
+        resize_receipt(
+            &receipt.to_account_info(),
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            receipt.held_tickets()
+        )?;
+        receipt.tickets.push(ctx.accounts.listing.ticket_number);
+        receipt.listed_tickets -= 1;
+        Ok(())
+    }
+
+    /**
+     * Buy a listed ticket, the price goes to the seller less the royalty paid to the fee account
+     * @param ctx is the context of the program
+     * @param allowlist_proof is the merkle proof of the signer's allowlist leaf, empty for public rounds
+     * @param allowance is the ticket allowance of the signer's allowlist leaf, 0 for none
+     * @return the result of the operation
+     */
+    pub fn buy_listed_ticket(ctx: Context<BuyListedTicket>, allowlist_proof: Vec<[u8; 32]>, allowance: u32) -> Result<()> {
+        let lottery = &ctx.accounts.lottery;
+        // listings are void once the winners are drawn
+        if lottery.status != LotteryStatus::Running {
+            return err!(ErrCode::ListingVoided);
+        }
+        // and can't be bought once the draw can be predicted, buyers could pick off the winners
+        lottery.check_sales_open(&ctx.accounts.clock)?;
+        if ctx.accounts.signer.key() == ctx.accounts.seller.key() {
+            return err!(ErrCode::InvalidArgus);
+        }
 
+        let buyer_receipt = &mut ctx.accounts.buyer_receipt;
+        buyer_receipt.open(lottery.key(), ctx.accounts.signer.key(), ctx.bumps.buyer_receipt);
+        // the buying wallet is held to the same cap, allowlist and gate as in buy_tickets
+        lottery.check_ticket_cap(buyer_receipt.held_tickets(), 1)?;
+        lottery.check_entry(&ctx.accounts.signer.key(), ctx.accounts.gate_token.as_deref(), &allowlist_proof, allowance, buyer_receipt.held_tickets() + 1)?;
+        resize_receipt(
+            &buyer_receipt.to_account_info(),
+            &ctx.accounts.signer.to_account_info(),
+            &ctx.accounts.system_program.to_account_info(),
+            buyer_receipt.held_tickets() + 1
+        )?;
+
+        let price = ctx.accounts.listing.price;
+        let royalty_amount = royalty_of(price, ctx.accounts.app_stats.royalty_percent)?;
+
+        // transfer royalty to fee account
+        if royalty_amount > 0 {
+            let cpi_accounts = Transfer {
+                from: ctx.accounts.buyer_token.to_account_info(),
+                to: ctx.accounts.fee_token.to_account_info(),
+                authority: ctx.accounts.signer.to_account_info(),
+            };
+            let cpi_program = ctx.accounts.token_program.to_account_info();
+            token::transfer(CpiContext::new(cpi_program, cpi_accounts), royalty_amount)?;
+        }
 
-        // transfer tokens from buyer to prize account
+        // transfer the rest of the price to the seller
         let cpi_accounts = Transfer {
-            from: ctx.accounts.creator_token.to_account_info(),
-            to: ctx.accounts.prize.to_account_info(),
+            from: ctx.accounts.buyer_token.to_account_info(),
+            to: ctx.accounts.seller_token.to_account_info(),
             authority: ctx.accounts.signer.to_account_info(),
         };
         let cpi_program = ctx.accounts.token_program.to_account_info();
-        let cpi_ctx: CpiContext<Transfer> = CpiContext::new(cpi_program, cpi_accounts);
-        token::transfer(cpi_ctx, real_amount)?;
-
-        for _ in 0..ticket_amount {
-            // todo update this with RGN 
-            let slot = ctx.accounts.clock.unix_timestamp as u64;
-            let n = get_sha256_hashed_random(slot,lottery.left_tickets.len() as u64);
-            let random_number = (n as usize) % lottery.left_tickets.len();
-
-            // Determine the index of the existing buyer, if any
-            let buyer_index = lottery.buyers.iter().position(|buyer| buyer.participant == ctx.accounts.signer.key());
-
-            // Clone the ticket number to be added since it will be used in both match arms
-            let ticket_to_add = lottery.left_tickets[random_number].clone();
-
-            match buyer_index {
-                Some(index) => {
-                    // Temporarily remove the buyer to bypass the borrow checker
-                    let mut buyer = lottery.buyers.remove(index);
-
-                    // check if the buyer has reached the maximum number of tickets
-                    if buyer.tickets.len() + ticket_amount as usize >= lottery.max_tickets_per_buyer as usize {
-                        return err!(ErrCode::MaxTicketsPerBuyer);
-                    }
-
-                    buyer.tickets.push(ticket_to_add);
-                    // Re-insert the buyer back at the same index
-                    lottery.buyers.insert(index, buyer);
-                },
-                None => {
-                    // check if the buyer has reached the maximum number of tickets
-                    if ticket_amount as usize >= lottery.max_tickets_per_buyer as usize {
-                        return err!(ErrCode::MaxTicketsPerBuyer);
-                    }
-                    // If the buyer does not exist, create a new buyer and add them to the list
-                    let buyer = Buyer {
-                        participant: ctx.accounts.signer.key(),
-                        tickets: vec![ticket_to_add],
-                    };
-                    lottery.buyers.push(buyer);
-                },
-            }
-            
-            lottery.left_tickets.remove(random_number);
+        token::transfer(CpiContext::new(cpi_program, cpi_accounts), price - royalty_amount)?;
+
+        let ticket_number = ctx.accounts.listing.ticket_number;
+        ctx.accounts.seller_receipt.listed_tickets -= 1;
+        buyer_receipt.tickets.push(ticket_number);
+
+        emit!(TicketTransferred {
+            lottery: lottery.key(),
+            ticket_number,
+            from: ctx.accounts.seller.key(),
+            to: ctx.accounts.signer.key(),
+        });
+        Ok(())
+    }
+
+    /**
+     * Mint a ticket of the buyer's receipt as a non-fungible SPL token (supply 1, decimals 0).
+     * Ticket numbers are picked on chain during buy_tickets, so the mint accounts can't be
+     * passed to that instruction and each ticket is minted here once its number is known.
+     * The ticket leaves the receipt, from then on the token holder claims it with claim_ticket_prize.
+     * @param ctx is the context of the program
+     * @param ticket_number is the ticket to mint, it must be on the signer's receipt
+     * @return the result of the operation
+     */
+    pub fn mint_ticket(ctx: Context<MintTicket>, ticket_number: u32) -> Result<()> {
+        let lottery = &ctx.accounts.lottery;
+        if !lottery.nft_tickets {
+            return err!(ErrCode::NftTicketsDisabled);
+        }
+
+        let receipt = &mut ctx.accounts.receipt;
+        if receipt.claimed || receipt.refunded {
+            return err!(ErrCode::AlreadyClaimd);
         }
-        // lottery.collected += real_amount;
-        lottery.collected += ticket_price * ticket_amount; // todo: less fees
+        let index = receipt.tickets.iter()
+            .position(|&ticket| ticket == ticket_number)
+            .ok_or(ErrCode::InvalidBuyer)?;
+        receipt.tickets.remove(index);
+        // still counts against the owner's caps, minting can't be used to buy past them
+        receipt.minted_tickets += 1;
+
+        // the mint is its own authority, revoked once the single token exists
+        let binding: Pubkey = lottery.key();
+        let ticket_bytes = ticket_number.to_le_bytes();
+        let seeds: &[&[u8]; 4] = &[b"ticket-mint".as_ref(), binding.as_ref(), ticket_bytes.as_ref(), &[ctx.bumps.ticket_mint]];
+        let signer: &[&[&[u8]]; 1] = &[&seeds[..]];
+
+        let cpi_accounts = MintTo {
+            mint: ctx.accounts.ticket_mint.to_account_info(),
+            to: ctx.accounts.ticket_token.to_account_info(),
+            authority: ctx.accounts.ticket_mint.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::mint_to(CpiContext::new(cpi_program, cpi_accounts).with_signer(signer), 1)?;
+
+        let cpi_accounts = SetAuthority {
+            current_authority: ctx.accounts.ticket_mint.to_account_info(),
+            account_or_mint: ctx.accounts.ticket_mint.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::set_authority(CpiContext::new(cpi_program, cpi_accounts).with_signer(signer), AuthorityType::MintTokens, None)?;
         Ok(())
     }
 
-    pub fn reveal_winners(ctx: Context<RevealWinner>) -> Result<()> {
-        // check lottery status
+    /**
+     * Change the most tickets one wallet can hold while the round is running,
+     * wallets already over a lowered cap keep their tickets but cannot get more.
+     * Rounds with ticket tokens can't be capped, see create_lottery
+     * @param ctx is the context of the program
+     * @param max_tickets_per_buyer is the new cap, 0 for no limit
+     * @return the result of the operation
+     */
+    pub fn update_max_tickets_per_buyer(ctx: Context<UpdateMaxTicketsPerBuyer>, max_tickets_per_buyer: u32) -> Result<()> {
         let lottery = &mut ctx.accounts.lottery;
-        let now = ctx.accounts.clock.unix_timestamp as i64;
-        let end_ts = lottery.end;
-        if now > end_ts {
-            // TODO: if tickets sold are lower than 10, we set lottery as unresolved
-            // and allow users withdraw his tickets 
-            if lottery.ticket_amount as usize - lottery.left_tickets.len() < 10 {
-                lottery.status = LotteryStatus::Unresolved;
-            } else {
-                lottery.status = LotteryStatus::Ended;
-            }
-        } 
-
-        // check if buyers list is empty
-        let buyers_len = lottery.buyers.len(); 
-        if buyers_len == 0 {
-            //return err!(ErrCode::BuyerListEmpty);
-            lottery.status = LotteryStatus::Unresolved;
-            return Ok(());
+        if lottery.status != LotteryStatus::Running {
+            return err!(ErrCode::LotteryNotRunning);
+        }
+        if lottery.nft_tickets && max_tickets_per_buyer != 0 {
+            return err!(ErrCode::RestrictedNftTickets);
         }
+        lottery.max_tickets_per_buyer = max_tickets_per_buyer;
+        Ok(())
+    }
 
-        let slot = ctx.accounts.clock.unix_timestamp as u64;
-        let mut temp_winners: Vec<Winner> = Vec::new(); // Temporary vector to hold winners
+    /**
+     * Start the draw from the committed slot hash, mixed with the revealed secret in commit-reveal rounds.
+     * The operator knows the secret from the start, the slot hash keeps it from steering the draw with
+     * the last client seed. The hash is read from SlotHashes, or from the lottery once save_slot_hash
+     * stored it, so the secret can be revealed until reveal_deadline. A commit-reveal round cannot be
+     * recommitted and turns unresolved after reveal_deadline instead, so holding the secret back never
+     * buys the operator a second draw.
+     * Runs the first batch of the draw, continue_draw runs the others
+     * @param ctx is the context of the program
+     * @param secret is the preimage of lottery.secret_commitment, none for slot-hash rounds
+     * @return the result of the operation
+     */
+    pub fn reveal_winners(ctx: Context<RevealWinner>, secret: Option<[u8; 32]>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        if lottery.randomness_source == RandomnessSource::Oracle {
+            return err!(ErrCode::WrongRandomnessSource);
+        }
 
-        // TODO: if buyers_len is odd, we will send one ticket to fee account
-        for _ in 0..(buyers_len / 2) {
-            let random_number = slot % (buyers_len as u64);
-            // let n = get_sha256_hashed_random(slot,lottery.left_tickets.len() as u64);
-            // let random_number = (n as usize) % lottery.left_tickets.len();
-            let winner = Winner {
-                participant: lottery.buyers[random_number as usize].participant,
-                claimed: false,
-                claimed_amount: 0,
-            };
-            temp_winners.push(winner);
+        let now = ctx.accounts.clock.unix_timestamp;
+        if !lottery.check_draw_window(&ctx.accounts.clock)? {
+            ctx.accounts.draw_record.record_missed_deadline(lottery, now, ctx.bumps.draw_record);
+            return Ok(());
         }
 
-        lottery.winners.extend(temp_winners);
+        let slot_hash = if lottery.saved_slot_hash != [0u8; 32] {
+            lottery.saved_slot_hash
+        } else {
+            target_slot_hash(&ctx.accounts.slot_hashes.try_borrow_data()?, lottery.target_slot, ctx.accounts.clock.slot)?
+        };
+        let randomness = match lottery.randomness_source {
+            RandomnessSource::CommitReveal => {
+                let secret = secret.ok_or(ErrCode::InvalidSecret)?;
+                if hash(&secret).to_bytes() != lottery.secret_commitment {
+                    return err!(ErrCode::InvalidSecret);
+                }
+                secret
+            },
+            _ => slot_hash,
+        };
 
+        // the program seeds the draw from the record, the same way a verifier does
+        let draw_record = &mut ctx.accounts.draw_record;
+        draw_record.record_inputs(lottery, &randomness, &slot_hash, now, ctx.bumps.draw_record);
+        let (ticket_pool, mut pool_tickets) = TicketPool::load_mut(&ctx.accounts.ticket_pool)?;
+        lottery.start_draw(&ticket_pool, &mut pool_tickets, draw_record);
         Ok(())        
     }
 
-    pub fn claim_prize(ctx: Context<ClaimPrize>) -> Result<()> {
-        let app_stats = &mut ctx.accounts.app_stats;
-        let fee_percent = app_stats.fee_percent;
-        let mut claimable_amount = 0;
-    
-        //loop through lottery rounds
-        for round in 0..app_stats.current_round {
-            let round_key = app_stats.current_round_list[round as usize];
-            // Get a mutable reference to the lottery
-            //let lottery = app_stats.lotteries.get_mut(0).unwrap();
-            let lottery = app_stats.lotteries.get_mut(&round_key).unwrap();
-
-            // check if lottery is running, if yes, skip
-            if lottery.status == LotteryStatus::Running {
-                continue;
-            }
-
-            // check if lottery is unresolved
-            if lottery.status == LotteryStatus::Unresolved {
-                // check if user is on the buyer list
-                let mut is_buyer = false;
-                let mut buyer_index = 0;
-                for (index, buyer) in lottery.buyers.iter().enumerate() {
-                    if buyer.participant == ctx.accounts.user.key() {
-                        is_buyer = true;
-                        buyer_index = index;
-                        break;
-                    }
-                }
+    /**
+     * Commit a slot-hash round to a new target slot once the previous hash rotated out
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn recommit_target_slot(ctx: Context<RecommitTargetSlot>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        if lottery.randomness_source != RandomnessSource::SlotHash {
+            return err!(ErrCode::WrongRandomnessSource);
+        }
+        if lottery.status != LotteryStatus::Running {
+            return err!(ErrCode::LotteryNotRunning);
+        }
+        let now = ctx.accounts.clock.unix_timestamp;
+        if now <= lottery.end {
+            return err!(ErrCode::RoundNotEnded);
+        }
 
-                if !is_buyer {
-                    return err!(ErrCode::InvalidBuyer);
-                }
+        // only a target whose hash can no longer be read may be replaced
+        if lottery.saved_slot_hash != [0u8; 32] {
+            return err!(ErrCode::SlotHashAvailable);
+        }
+        let current_slot = ctx.accounts.clock.slot;
+        match target_slot_hash(&ctx.accounts.slot_hashes.try_borrow_data()?, lottery.target_slot, current_slot) {
+            Err(e) if e == error!(ErrCode::SlotHashExpired) => {},
+            Err(e) => return Err(e),
+            Ok(_) => return err!(ErrCode::SlotHashAvailable),
+        }
 
-                // calculate claimable amount, will be ticket price * number of tickets bought
-                claimable_amount += lottery.ticket_price * (lottery.buyers[buyer_index].tickets.len() as u64);
-                // substract fee from claimable amount
-                claimable_amount -= (claimable_amount * fee_percent as u64) / 100;
-                lottery.buyers.remove(buyer_index);
-            }
+        lottery.target_slot = current_slot + SLOT_HASH_DELAY;
+        Ok(())
+    }
 
-            // check if lottery is ended
-            if lottery.status == LotteryStatus::Ended {
-                // check if user is on the winner list
-                let mut is_winner = false;
-                let mut winner_index = 0;
-                for (index, winner) in lottery.winners.iter().enumerate() {
-                    if winner.participant == ctx.accounts.user.key() {
-                        is_winner = true;
-                        winner_index = index;
-                        break;
-                    }
-                }
+    /**
+     * Store the target slot hash in the lottery while it is in the SlotHashes window, which only
+     * keeps the last 512 slots. Anyone can call it, reveal_winners then draws from the stored hash.
+     * The reveal window runs for REVEAL_WINDOW from here when the slots reached the target late
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn save_slot_hash(ctx: Context<SaveSlotHash>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        if lottery.randomness_source == RandomnessSource::Oracle {
+            return err!(ErrCode::WrongRandomnessSource);
+        }
+        if lottery.status != LotteryStatus::Running {
+            return err!(ErrCode::LotteryNotRunning);
+        }
+        if lottery.saved_slot_hash != [0u8; 32] {
+            return err!(ErrCode::SlotHashSaved);
+        }
+        let now = ctx.accounts.clock.unix_timestamp;
+        if now > lottery.reveal_deadline {
+            return err!(ErrCode::RevealWindowClosed);
+        }
 
-                if !is_winner {
-                    return err!(ErrCode::InvalidWinner);
-                }
+        let slot_hash = target_slot_hash(&ctx.accounts.slot_hashes.try_borrow_data()?, lottery.target_slot, ctx.accounts.clock.slot)?;
+        lottery.save_slot_hash(slot_hash, now);
+        Ok(())
+    }
 
-                // check if prize is already claimed
-                if lottery.winners[winner_index].claimed {
-                    continue;
-                }
+    /**
+     * Bind the round to an unfulfilled VRF result account of the lottery oracle
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn request_randomness(ctx: Context<RequestRandomness>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        if lottery.randomness_source != RandomnessSource::Oracle {
+            return err!(ErrCode::WrongRandomnessSource);
+        }
+        if lottery.status != LotteryStatus::Running {
+            return err!(ErrCode::LotteryNotRunning);
+        }
 
-                // calculate claimable amount
-                // calculate amount, for now we will use the collected amount divided number of winners
-                // todo: we should calculate amount based on number of tickets sold, not number of winners
-                let amount = lottery.collected / (lottery.winners.len() as u64);
-                lottery.claimed_amount += amount;
-                lottery.winners[winner_index].claimed = true;
-                lottery.winners[winner_index].claimed_amount = amount;
-            }
+        // the ticket set must be final before the randomness is requested
+        let now = ctx.accounts.clock.unix_timestamp;
+        if now <= lottery.end {
+            return err!(ErrCode::RoundNotEnded);
+        }
+        if lottery.randomness_account != Pubkey::default() {
+            return err!(ErrCode::RandomnessAlreadyRequested);
         }
 
-        // check if claimable amount is zero
-        if claimable_amount == 0 {
-            return err!(ErrCode::ClaimableAmountIsZero);
+        let result = VrfResult::load(&ctx.accounts.randomness_account, &lottery.oracle_program)?;
+        if result.requester != lottery.key() {
+            return err!(ErrCode::InvalidRandomnessAccount);
+        }
+        if result.fulfilled {
+            return err!(ErrCode::RandomnessAlreadyFulfilled);
         }
 
-        // format recipient token account if empty
-        if ctx.accounts.user_token.data_is_empty() {
-            let cpi_accounts = Create {
-                payer: ctx.accounts.user.to_account_info(),
-                associated_token: ctx.accounts.user_token.clone(),
-                authority: ctx.accounts.user.to_account_info(),
-                mint: ctx.accounts.mint.to_account_info(),
-                system_program: ctx.accounts.system_program.to_account_info(),
-                token_program: ctx.accounts.token_program.to_account_info(),
-            };
-            let cpi_program = ctx.accounts.associated_token_program.to_account_info();
-            let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-            associated_token::create(cpi_ctx)?;
-        }
-
-            // // send token
-            // let nonce: u8 = lottery.prize_bump;
-            // //let lottery = &mut ctx.accounts.lottery;
-            // //let binding: Pubkey = lottery.key();
-            // let binding: Pubkey = round_key;
-            // let seeds: &[&[u8]; 3] = &[b"prize".as_ref(), binding.as_ref(), &[nonce]];
-            // let signer: &[&[&[u8]]; 1] = &[&seeds[..]];
-
-            // let cpi_accounts: Transfer<'_> = Transfer {
-            //     from: ctx.accounts.prize.to_account_info(),
-            //     to: ctx.accounts.user_token.to_account_info(),
-            //     authority: ctx.accounts.prize.to_account_info(),
-            // };
-            // let cpi_program: AccountInfo<'_> = ctx.accounts.token_program.to_account_info();
-            // let cpi_ctx: CpiContext<'_, '_, '_, '_, Transfer<'_>> = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer);
-            // token::transfer(cpi_ctx, lottery.claimed_amount)?;
-        
-            Ok(())
-}  
-}
+        lottery.randomness_account = ctx.accounts.randomness_account.key();
+        Ok(())
+    }
 
-#[account]
-pub struct AppStats {
-    pub fee_account: Pubkey,
-    pub fee_percent: u8,
-    pub owner: Pubkey,
-    pub admin: Pubkey,
-    pub current_round: u64,
-    pub current_round_key: Pubkey,
-    pub current_round_list: Vec<Pubkey>,
-    //pub lotteries: Vec<Lottery>,
-    pub lotteries: HashMap<Pubkey, Lottery>,
-    //pub mint: Pubkey,
-    bump: u8,
-}
+    /**
+     * Start the draw from the fulfilled VRF result requested in request_randomness,
+     * runs its first batch like reveal_winners
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn consume_randomness(ctx: Context<ConsumeRandomness>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        if lottery.randomness_source != RandomnessSource::Oracle {
+            return err!(ErrCode::WrongRandomnessSource);
+        }
 
-#[derive(Accounts)]
-pub struct CreateAppStats<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
-   
-    /// CHECK:don't read and write this account
-    pub admin_account:  AccountInfo<'info>,
+        let now = ctx.accounts.clock.unix_timestamp;
+        if !lottery.check_draw_window(&ctx.accounts.clock)? {
+            ctx.accounts.draw_record.record_missed_deadline(lottery, now, ctx.bumps.draw_record);
+            return Ok(());
+        }
 
-    /// CHECK:don't read and write this account
-    pub fee_account: AccountInfo<'info>,
+        if lottery.randomness_account == Pubkey::default() {
+            return err!(ErrCode::RandomnessNotRequested);
+        }
+        let result = VrfResult::load(&ctx.accounts.randomness_account, &lottery.oracle_program)?;
+        if !result.fulfilled {
+            return err!(ErrCode::RandomnessNotFulfilled);
+        }
 
-    pub mint: Account<'info, Mint>,
+        let draw_record = &mut ctx.accounts.draw_record;
+        draw_record.record_inputs(lottery, &result.randomness, &[0u8; 32], now, ctx.bumps.draw_record);
+        let (ticket_pool, mut pool_tickets) = TicketPool::load_mut(&ctx.accounts.ticket_pool)?;
+        lottery.start_draw(&ticket_pool, &mut pool_tickets, draw_record);
+        Ok(())
+    }
 
-    #[account(
-        init,
-        payer = signer,
-        space = 8 + 32 * 2 + 2 + 32 + 8 + 32 + 88,
-        seeds = [b"app-stats", signer.key().as_ref()],
-        bump
-    )]
-    pub app_stats: Account<'info, AppStats>,
-    pub system_program: Program<'info, System>,
-}
+    /**
+     * Run the next batch of a draw started by reveal_winners or consume_randomness, anyone can call it
+     * until the round has ended. A batch goes through DRAW_BATCH sold tickets, or draws DRAW_BATCH
+     * raffle winners, so rounds of any size are drawn within the compute limit
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn continue_draw(ctx: Context<ContinueDraw>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        if lottery.status != LotteryStatus::Drawing {
+            return err!(ErrCode::NotDrawing);
+        }
 
-#[derive(Accounts)]
-pub struct UpdateAppStats<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
+        let (ticket_pool, mut pool_tickets) = TicketPool::load_mut(&ctx.accounts.ticket_pool)?;
+        lottery.draw_batch(&ticket_pool, &mut pool_tickets, &mut ctx.accounts.draw_record);
+        Ok(())
+    }
 
-    /// CHECK:don't read and write this account
-    pub fee_account: AccountInfo<'info>,
+    /**
+     * Move a round whose randomness never arrived to the refundable state, writing its draw record
+     * the same way reveal_winners and consume_randomness do past the reveal deadline
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn update_lottery_status(ctx: Context<UpdateLotteryStatus>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        let now = ctx.accounts.clock.unix_timestamp;
+        if lottery.check_draw_window(&ctx.accounts.clock)? {
+            return err!(ErrCode::RevealWindowOpen);
+        }
 
-    #[account(
-        mut, 
+        ctx.accounts.draw_record.record_missed_deadline(lottery, now, ctx.bumps.draw_record);
+        Ok(())
+    }
+
+    /**
+     * Claim the prize of the winning tickets of a drawn round, or the refund of an unresolved one
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn claim_prize(ctx: Context<ClaimPrize>) -> Result<()> {
+        let lottery = &ctx.accounts.lottery;
+        let receipt = &mut ctx.accounts.receipt;
+
+        if receipt.claimed || receipt.refunded {
+            return err!(ErrCode::AlreadyClaimd);
+        }
+        // escrowed tickets must be back on the receipt before it is settled
+        if receipt.listed_tickets > 0 {
+            return err!(ErrCode::TicketListed);
+        }
+
+        let (ticket_pool, pool_tickets) = TicketPool::load(&ctx.accounts.ticket_pool)?;
+        let claimable_amount = match lottery.status {
+            LotteryStatus::Running | LotteryStatus::Drawing => return err!(ErrCode::RoundNotEnded),
+            // the round did not run, the buyer gets back what the tickets paid less fees
+            LotteryStatus::Unresolved => {
+                receipt.refunded = true;
+                receipt.tickets.iter()
+                    .map(|&ticket| ticket_pool.paid_for(&pool_tickets, ticket))
+                    .sum()
+            },
+            LotteryStatus::Ended => {
+                let amount: u64 = receipt.tickets.iter()
+                    .map(|&ticket| lottery.ticket_prize(&ticket_pool, &pool_tickets, ticket))
+                    .sum();
+                if amount == 0 {
+                    return err!(ErrCode::InvalidWinner);
+                }
+                receipt.claimed = true;
+                amount
+            },
+        };
+
+        // check if claimable amount is zero
+        if claimable_amount == 0 {
+            return err!(ErrCode::ClaimableAmountIsZero);
+        }
+        receipt.claimed_amount = claimable_amount;
+
+        let accounts = &ctx.accounts;
+        pay_from_prize(
+            lottery,
+            &accounts.prize,
+            &accounts.user,
+            &accounts.user_token,
+            &accounts.mint,
+            &accounts.token_program,
+            &accounts.associated_token_program,
+            &accounts.system_program,
+            claimable_amount
+        )
+    }
+
+    /**
+     * Claim the prize or the refund of a minted ticket, paid to the holder of its token.
+     * The ticket token is burned so it can't be claimed twice.
+     * @param ctx is the context of the program
+     * @param ticket_number is the ticket minted with mint_ticket
+     * @return the result of the operation
+     */
+    pub fn claim_ticket_prize(ctx: Context<ClaimTicketPrize>, ticket_number: u32) -> Result<()> {
+        let lottery = &ctx.accounts.lottery;
+
+        if ctx.accounts.ticket_token.amount != 1 {
+            return err!(ErrCode::InvalidBuyer);
+        }
+
+        let (ticket_pool, pool_tickets) = TicketPool::load(&ctx.accounts.ticket_pool)?;
+        let claimable_amount = match lottery.status {
+            LotteryStatus::Running | LotteryStatus::Drawing => return err!(ErrCode::RoundNotEnded),
+            // the round did not run, the holder gets back what the ticket paid less fees
+            LotteryStatus::Unresolved => ticket_pool.paid_for(&pool_tickets, ticket_number),
+            LotteryStatus::Ended => {
+                let amount = lottery.ticket_prize(&ticket_pool, &pool_tickets, ticket_number);
+                if amount == 0 {
+                    return err!(ErrCode::InvalidWinner);
+                }
+                amount
+            },
+        };
+
+        // check if claimable amount is zero
+        if claimable_amount == 0 {
+            return err!(ErrCode::ClaimableAmountIsZero);
+        }
+
+        let cpi_accounts = Burn {
+            mint: ctx.accounts.ticket_mint.to_account_info(),
+            from: ctx.accounts.ticket_token.to_account_info(),
+            authority: ctx.accounts.user.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::burn(CpiContext::new(cpi_program, cpi_accounts), 1)?;
+
+        let accounts = &ctx.accounts;
+        pay_from_prize(
+            lottery,
+            &accounts.prize,
+            &accounts.user,
+            &accounts.user_token,
+            &accounts.mint,
+            &accounts.token_program,
+            &accounts.associated_token_program,
+            &accounts.system_program,
+            claimable_amount
+        )
+    }
+
+    /**
+     * Send the pots of the pick-numbers tiers nobody won and the remainders of the tier splits
+     * to the lottery rollover account, see Lottery::rollover_amount
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn sweep_rollover(ctx: Context<SweepRollover>) -> Result<()> {
+        let lottery = &mut ctx.accounts.lottery;
+        // raffles pay every drawn ticket, only the tiers of a drawn pick-numbers round can go unwon
+        if lottery.mode != LotteryMode::PickNumbers || lottery.status != LotteryStatus::Ended {
+            return err!(ErrCode::NoRollover);
+        }
+        if lottery.rollover_swept {
+            return err!(ErrCode::AlreadyClaimd);
+        }
+
+        let amount = lottery.rollover_amount();
+        if amount == 0 {
+            return err!(ErrCode::ClaimableAmountIsZero);
+        }
+        lottery.rollover_swept = true;
+
+        transfer_from_prize(
+            lottery,
+            &ctx.accounts.prize,
+            &ctx.accounts.rollover_token.to_account_info(),
+            &ctx.accounts.token_program,
+            amount
+        )
+    }
+
+    /**
+     * Open the referral account of the signer for lotteries paid in `mint`,
+     * buyers then pass it with its vault to buy_tickets
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn create_referrer_stats(ctx: Context<CreateReferrerStats>) -> Result<()> {
+        let referrer_stats = &mut ctx.accounts.referrer_stats;
+        referrer_stats.referrer = ctx.accounts.signer.key();
+        referrer_stats.mint = ctx.accounts.mint.key();
+        referrer_stats.vault = ctx.accounts.referral_vault.key();
+        referrer_stats.referred_volume = 0;
+        referrer_stats.referred_tickets = 0;
+        referrer_stats.pending_rewards = 0;
+        referrer_stats.claimed_rewards = 0;
+        referrer_stats.bump = ctx.bumps.referrer_stats;
+        referrer_stats.vault_bump = ctx.bumps.referral_vault;
+        Ok(())
+    }
+
+    /**
+     * Pay the referral rewards accrued since the last claim to the referrer
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn claim_referral_rewards(ctx: Context<ClaimReferralRewards>) -> Result<()> {
+        let referrer_stats = &mut ctx.accounts.referrer_stats;
+        let amount = referrer_stats.pending_rewards;
+        if amount == 0 {
+            return err!(ErrCode::ClaimableAmountIsZero);
+        }
+        referrer_stats.pending_rewards = 0;
+        referrer_stats.claimed_rewards += amount;
+
+        // send token
+        let binding: Pubkey = referrer_stats.key();
+        let seeds: &[&[u8]; 3] = &[b"referral-vault".as_ref(), binding.as_ref(), &[referrer_stats.vault_bump]];
+        let signer: &[&[&[u8]]; 1] = &[&seeds[..]];
+
+        let cpi_accounts: Transfer<'_> = Transfer {
+            from: ctx.accounts.referral_vault.to_account_info(),
+            to: ctx.accounts.referrer_token.to_account_info(),
+            authority: ctx.accounts.referral_vault.to_account_info(),
+        };
+        let cpi_program = ctx.accounts.token_program.to_account_info();
+        token::transfer(CpiContext::new_with_signer(cpi_program, cpi_accounts, signer), amount)?;
+        Ok(())
+    }
+}
+
+// sends `amount` prize tokens to the user, creating their associated token account when empty
+#[allow(clippy::too_many_arguments)]
+fn pay_from_prize<'info>(
+    lottery: &Account<'info, Lottery>,
+    prize: &Account<'info, TokenAccount>,
+    user: &Signer<'info>,
+    user_token: &AccountInfo<'info>,
+    mint: &Account<'info, Mint>,
+    token_program: &Program<'info, Token>,
+    associated_token_program: &Program<'info, AssociatedToken>,
+    system_program: &Program<'info, System>,
+    amount: u64
+) -> Result<()> {
+    // format recipient token account if empty
+    if user_token.data_is_empty() {
+        let cpi_accounts = Create {
+            payer: user.to_account_info(),
+            associated_token: user_token.clone(),
+            authority: user.to_account_info(),
+            mint: mint.to_account_info(),
+            system_program: system_program.to_account_info(),
+            token_program: token_program.to_account_info(),
+        };
+        let cpi_program = associated_token_program.to_account_info();
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        associated_token::create(cpi_ctx)?;
+    }
+
+    transfer_from_prize(lottery, prize, user_token, token_program, amount)
+}
+
+// sends `amount` prize tokens to a token account, signed by the prize PDA
+fn transfer_from_prize<'info>(
+    lottery: &Account<'info, Lottery>,
+    prize: &Account<'info, TokenAccount>,
+    to: &AccountInfo<'info>,
+    token_program: &Program<'info, Token>,
+    amount: u64
+) -> Result<()> {
+    // send token
+    let nonce: u8 = lottery.prize_bump;
+    let binding: Pubkey = lottery.key();
+    let seeds: &[&[u8]; 3] = &[b"prize".as_ref(), binding.as_ref(), &[nonce]];
+    let signer: &[&[&[u8]]; 1] = &[&seeds[..]];
+
+    let cpi_accounts: Transfer<'_> = Transfer {
+        from: prize.to_account_info(),
+        to: to.clone(),
+        authority: prize.to_account_info(),
+    };
+    let cpi_program: AccountInfo<'_> = token_program.to_account_info();
+    let cpi_ctx: CpiContext<'_, '_, '_, '_, Transfer<'_>> = CpiContext::new(cpi_program, cpi_accounts).with_signer(signer);
+    token::transfer(cpi_ctx, amount)
+}
+
+#[account]
+pub struct AppStats {
+    pub fee_account: Pubkey,
+    pub fee_percent: u8,
+    pub owner: Pubkey,
+    pub admin: Pubkey,
+    pub current_round: u64,
+    pub current_round_key: Pubkey,
+    // the latest MAX_LISTED_ROUNDS rounds, oldest first
+    pub current_round_list: Vec<Pubkey>,
+    pub oracle_program: Pubkey,
+    // share of ticket resales paid to the fee account
+    pub royalty_percent: u8,
+    // share of ticket fees paid to referrers, see ReferrerStats
+    pub referral_percent: u8,
+    //pub lotteries: Vec<Lottery>,
+    pub lotteries: HashMap<Pubkey, Lottery>,
+    //pub mint: Pubkey,
+    bump: u8,
+}
+
+impl AppStats {
+    // the lotteries map is left empty
+    pub const SPACE: usize = 8 + 32 + 1 + 32 + 32 + 8 + 32 + (4 + 32 * MAX_LISTED_ROUNDS) + 32 + 1 + 1 + 4 + 1;
+}
+
+#[derive(Accounts)]
+pub struct CreateAppStats<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+   
+    /// CHECK:don't read and write this account
+    pub admin_account:  AccountInfo<'info>,
+
+    /// CHECK:don't read and write this account
+    pub fee_account: AccountInfo<'info>,
+
+    pub mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = AppStats::SPACE,
+        seeds = [b"app-stats", signer.key().as_ref()],
+        bump
+    )]
+    pub app_stats: Account<'info, AppStats>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateAppStats<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    /// CHECK:don't read and write this account
+    pub fee_account: AccountInfo<'info>,
+
+    #[account(
+        mut, 
         constraint=app_stats.owner == signer.key(),
         seeds = [b"app-stats", signer.key().as_ref()],
         bump = app_stats.bump
@@ -417,134 +1237,1513 @@ pub struct UpdateAppStats<'info> {
 }
 
 #[derive(Accounts)]
-pub struct CreateLottery<'info> {
-    #[account(mut)]
-    pub signer: Signer<'info>,
+pub struct CreateLottery<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(init, payer = signer, space = LOTTERY_SPACE)]
+    pub lottery: Box<Account<'info, Lottery>>,
+
+    // allocated by the client with TicketPool::space(ticket_amount, pick_count), at most MAX_TICKET_POOL_SPACE
+    #[account(zero)]
+    pub ticket_pool: AccountLoader<'info, TicketPool>,
+
+    #[account(
+        init,
+        seeds = [b"prize", lottery.key().as_ref()],
+        bump,
+        payer = signer,
+        owner = token_program.key(),
+        rent_exempt = enforce,
+        token::mint = mint,
+        token::authority = prize
+    )]
+    pub prize: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"proceeds", lottery.key().as_ref()], bump)]
+    pub proceeds: SystemAccount<'info>,
+
+    // receives what sweep_rollover sends, required for pick-numbers rounds
+    #[account(constraint = rollover_token.mint == mint.key() && rollover_token.owner == signer.key() @ ErrCode::InvalidRolloverAccount)]
+    pub rollover_token: Option<Box<Account<'info, TokenAccount>>>,
+    pub clock: Sysvar<'info, Clock>,
+    pub mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+    pub app_stats: Account<'info, AppStats>,
+
+    /// CHECK: don't read and write this account
+    pub admin_account: AccountInfo<'info>,
+}
+
+#[account]
+#[derive(Default)]
+pub struct Lottery {
+    pub creator: Pubkey,
+    pub start: i64,
+    pub end: i64,
+    // number of drawn tickets, which ones is kept in the ticket pool, see TicketPool::is_winner
+    pub winning_ticket_count: u32,
+    // drawn numbers of a pick-numbers round, in draw order
+    pub winning_numbers: Vec<u8>,
+    pub ticket_price: u64,
+    pub ticket_amount: u32,
+    pub prize_token: Pubkey,
+    // ticket numbers live in the ticket pool account, who holds them in TicketReceipt accounts
+    pub ticket_pool: Pubkey,
+    pub tickets_sold: u32,
+    pub prize_bump: u8,
+    pub proceeds_bump: u8,
+    pub collected: u64,
+    // 0 for no limit
+    pub max_tickets_per_buyer: u32,
+    // tickets can be minted as SPL tokens, see mint_ticket
+    pub nft_tickets: bool,
+    pub mode: LotteryMode,
+    pub pick_count: u8,
+    pub pick_range: u8,
+    // pick-numbers payouts, highest matches first
+    pub prize_tiers: Vec<PrizeTier>,
+    // tickets paid by each prize tier, counted at the draw
+    pub tier_winners: Vec<u32>,
+    // receives the pots of tiers without winners, see sweep_rollover
+    pub rollover_account: Pubkey,
+    pub rollover_swept: bool,
+    // bulk-discount bundles, buy_tickets charges the cheapest combination with single tickets
+    pub price_tiers: Vec<PriceTier>,
+    // merkle root of allowlist_leaf(wallet, allowance) leaves, zero when anyone can buy
+    pub allowlist_root: [u8; 32],
+    // token buyers must hold at least gate_min_balance of, default pubkey when not gated
+    pub gate_mint: Pubkey,
+    pub gate_min_balance: u64,
+    // share of a cancelled ticket's refund kept as a fee, see cancel_tickets
+    pub cancel_penalty_percent: u8,
+    pub status: LotteryStatus,
+    pub secret_commitment: [u8; 32],
+    pub reveal_deadline: i64,
+    pub oracle_program: Pubkey,
+    pub randomness_account: Pubkey,
+    pub randomness_source: RandomnessSource,
+    // slot whose hash seeds slot-hash and commit-reveal rounds, always after the ticket sales close
+    pub target_slot: u64,
+    // hash of target_slot once save_slot_hash stored it, zero before
+    pub saved_slot_hash: [u8; 32],
+    // running hash of the buyers' client seeds, mixed into the draw seed
+    pub entropy_accumulator: [u8; 32],
+}
+
+impl Lottery {
+    // a wallet holding `held` tickets may get `requested` more, up to max_tickets_per_buyer
+    fn check_ticket_cap(&self, held: usize, requested: usize) -> Result<()> {
+        if self.max_tickets_per_buyer != 0 && held + requested > self.max_tickets_per_buyer as usize {
+            return err!(ErrCode::MaxTicketsPerBuyer);
+        }
+        Ok(())
+    }
+
+    // private and gated rounds only let allowlisted holders of the gate token hold tickets, the same
+    // for buyers and for wallets receiving a ticket. `holding` is what `owner` holds afterwards, it must
+    // stay within the allowance of the owner's allowlist leaf, 0 for none.
+    // The gate token's mint is checked by the accounts, its owner here
+    fn check_entry(&self, owner: &Pubkey, gate_token: Option<&Account<TokenAccount>>, allowlist_proof: &[[u8; 32]], allowance: u32, holding: usize) -> Result<()> {
+        if self.gate_mint != Pubkey::default() {
+            match gate_token {
+                Some(gate_token) if gate_token.owner == *owner && gate_token.amount >= self.gate_min_balance => {},
+                _ => return err!(ErrCode::GateBalanceTooLow),
+            }
+        }
+
+        if self.allowlist_root != [0u8; 32] {
+            let leaf = allowlist_leaf(&owner.to_bytes(), allowance);
+            if !verify_proof(&self.allowlist_root, &leaf, allowlist_proof) {
+                return err!(ErrCode::NotAllowlisted);
+            }
+            if allowance != 0 && holding > allowance as usize {
+                return err!(ErrCode::MaxTicketsPerBuyer);
+            }
+        }
+        Ok(())
+    }
+
+    // sale window and draw deadline of a round of `round_duration` seconds created at `clock`,
+    // the target slot is where `end` falls when slots take MS_PER_SLOT
+    pub fn schedule(&mut self, clock: &Clock, round_duration: i64) {
+        self.start = clock.unix_timestamp;
+        self.end = clock.unix_timestamp + round_duration;
+        self.reveal_deadline = self.end + REVEAL_WINDOW;
+        self.target_slot = clock.slot + (round_duration * 1000 / MS_PER_SLOT) as u64 + SLOT_HASH_DELAY;
+    }
+
+    // slots slower than MS_PER_SLOT reach the target after `end`, the reveal window then runs
+    // from the time the hash is saved so the late target does not eat into it
+    pub fn save_slot_hash(&mut self, slot_hash: [u8; 32], now: i64) {
+        self.saved_slot_hash = slot_hash;
+        self.reveal_deadline = self.reveal_deadline.max(now + REVEAL_WINDOW);
+    }
+
+    // tickets change hands only until `end`, and in rounds drawn from a slot hash only until
+    // the target slot, once its hash exists anyone holding the other inputs can predict the draw
+    pub fn sales_closed(&self, clock: &Clock) -> bool {
+        clock.unix_timestamp > self.end
+            || (self.randomness_source != RandomnessSource::Oracle && clock.slot >= self.target_slot)
+    }
+
+    fn check_sales_open(&self, clock: &Clock) -> Result<()> {
+        if self.sales_closed(clock) {
+            return err!(ErrCode::RoundEnded);
+        }
+        Ok(())
+    }
+
+    // common checks before a draw, returns false when the round was moved to refundable instead.
+    // The draw may start as soon as the sales close: slots running faster than MS_PER_SLOT reach
+    // the target slot before `end`, and waiting for `end` could let its hash leave SlotHashes
+    pub fn check_draw_window(&mut self, clock: &Clock) -> Result<bool> {
+        if self.status != LotteryStatus::Running {
+            return err!(ErrCode::LotteryNotRunning);
+        }
+        if !self.sales_closed(clock) {
+            return err!(ErrCode::RoundNotEnded);
+        }
+
+        // the randomness missed the reveal window, buyers can get refunds
+        if clock.unix_timestamp > self.reveal_deadline {
+            self.status = LotteryStatus::Unresolved;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    // starts the draw seeded from `record` and runs its first batch, the round is drawing until
+    // draw_batch ends it. Rounds with fewer than 10 tickets sold turn unresolved instead.
+    // Shared with the off-chain verifier, which replays the whole draw over a copy of the pool
+    pub fn start_draw(&mut self, ticket_pool: &TicketPool, pool_tickets: &mut [u8], record: &mut DrawRecord) {
+        // if tickets sold are lower than 10, we set lottery as unresolved
+        // and allow users withdraw his tickets, the few tickets are still recorded
+        if self.tickets_sold < 10 {
+            record.sold_tickets_hash = ticket_pool.sold_snapshot_hash(pool_tickets);
+            record.hashed_ticket_count = self.tickets_sold;
+            self.status = LotteryStatus::Unresolved;
+            record.record_outcome(self);
+            return;
+        }
+
+        if self.mode == LotteryMode::PickNumbers {
+            self.winning_numbers = drawn_numbers(self.pick_count, self.pick_range, &record.stream_seed());
+            self.tier_winners = vec![0u32; self.prize_tiers.len()];
+        }
+        self.winning_ticket_count = 0;
+        self.status = LotteryStatus::Drawing;
+        record.record_outcome(self);
+        self.draw_batch(ticket_pool, pool_tickets, record);
+    }
+
+    // next batch of a started draw, ends the round after the last one. The sold slots are hashed
+    // first, DRAW_BATCH at a time and in slot order, pick-numbers rounds count and mark the winners
+    // on the way. Raffles then draw DRAW_BATCH tickets at a time, see TicketPool::draw_tickets
+    pub fn draw_batch(&mut self, ticket_pool: &TicketPool, pool_tickets: &mut [u8], record: &mut DrawRecord) {
+        let first_sold = ticket_pool.remaining as usize;
+        if record.hashed_ticket_count < self.tickets_sold {
+            let start = record.hashed_ticket_count;
+            let end = self.tickets_sold.min(start + DRAW_BATCH);
+            let slots = first_sold + start as usize..first_sold + end as usize;
+            record.sold_tickets_hash = ticket_pool.sold_tickets_hash(pool_tickets, slots.clone(), &record.sold_tickets_hash);
+            record.hashed_ticket_count = end;
+            if self.mode == LotteryMode::Raffle {
+                return;
+            }
+
+            // tickets reaching any prize tier win, pots of empty tiers go to the rollover account
+            let mut drawn = Vec::new();
+            for slot in slots {
+                let ticket = TicketPool::ticket_at(pool_tickets, slot);
+                if let Some(tier) = self.best_tier(match_count(ticket_pool.picks_of(pool_tickets, ticket), &self.winning_numbers)) {
+                    self.tier_winners[tier] += 1;
+                    drawn.push(ticket);
+                }
+            }
+            self.record_drawn(ticket_pool, pool_tickets, record, &drawn);
+            if end == self.tickets_sold {
+                self.status = LotteryStatus::Ended;
+                record.record_outcome(self);
+            }
+            return;
+        }
+
+        // draw over sold ticket numbers so odds follow the share of tickets held
+        // TODO: if sold tickets are odd, we will send one ticket to fee account
+        let winning_tickets = self.tickets_sold / 2;
+        let mut stream = record.stream();
+        let count = DRAW_BATCH.min(winning_tickets - record.drawn_ticket_count);
+        let drawn = ticket_pool.draw_tickets(pool_tickets, &mut stream, record.drawn_ticket_count, count);
+        record.stream_position = stream.position();
+        self.record_drawn(ticket_pool, pool_tickets, record, &drawn);
+        if record.drawn_ticket_count == winning_tickets {
+            self.status = LotteryStatus::Ended;
+            record.record_outcome(self);
+        }
+    }
+
+    // marks the tickets a draw batch picked as winners
+    fn record_drawn(&mut self, ticket_pool: &TicketPool, pool_tickets: &mut [u8], record: &mut DrawRecord, drawn: &[u32]) {
+        for &ticket in drawn.iter() {
+            ticket_pool.set_winner(pool_tickets, ticket, true);
+        }
+        self.winning_ticket_count += drawn.len() as u32;
+        record.drawn_ticket_count += drawn.len() as u32;
+        record.drawn_tickets_hash = drawn_tickets_hash(&record.drawn_tickets_hash, drawn);
+    }
+
+    // cheapest price of `ticket_amount` tickets combining the bundles of the price schedule
+    // with single tickets at ticket_price.
+    // Only the prices of the last `largest bundle` amounts are kept, heap memory is never freed
+    // on chain, so a table of the whole order would run out of it on large orders.
+    // Fails when the order costs more than a u64 holds
+    pub fn price_of(&self, ticket_amount: u64) -> Result<u64> {
+        let window = match self.price_tiers.iter().map(|tier| tier.tickets as u64).max() {
+            Some(largest) => largest,
+            None => return self.ticket_price.checked_mul(ticket_amount).ok_or(error!(ErrCode::InvalidArgus)),
+        };
+        // cheapest price of `tickets` tickets at `tickets % window`, zero tickets cost nothing
+        let mut cheapest = vec![0u64; window as usize];
+        let mut price = 0;
+        for tickets in 1..=ticket_amount {
+            price = cheapest[((tickets - 1) % window) as usize]
+                .checked_add(self.ticket_price)
+                .ok_or(error!(ErrCode::InvalidArgus))?;
+            for tier in self.price_tiers.iter() {
+                let size = tier.tickets as u64;
+                if size <= tickets {
+                    // a bundle overflowing where single tickets don't is never the cheaper one
+                    price = cheapest[((tickets - size) % window) as usize]
+                        .checked_add(tier.price)
+                        .map_or(price, |bundle_price| price.min(bundle_price));
+                }
+            }
+            cheapest[(tickets % window) as usize] = price;
+        }
+        Ok(price)
+    }
+
+    // first prize tier a ticket with `matches` drawn numbers reaches
+    pub fn best_tier(&self, matches: usize) -> Option<usize> {
+        self.prize_tiers.iter().position(|tier| tier.matches as usize <= matches)
+    }
+
+    // share of the collected amount set aside for a prize tier
+    pub fn tier_pot(&self, tier: usize) -> u64 {
+        self.collected * self.prize_tiers[tier].percent as u64 / 100
+    }
+
+    // what no ticket_prize claims, sent to the rollover account by sweep_rollover: the pots of the
+    // prize tiers nobody won, what the even splits of the others leave over, and what rounding the
+    // tier percents of the collected amount left out of every pot
+    pub fn rollover_amount(&self) -> u64 {
+        let pots: u64 = (0..self.prize_tiers.len()).map(|tier| self.tier_pot(tier)).sum();
+        let unclaimed: u64 = (0..self.prize_tiers.len())
+            .map(|tier| match self.tier_winners[tier] {
+                0 => self.tier_pot(tier),
+                winners => self.tier_pot(tier) % winners as u64,
+            })
+            .sum();
+        unclaimed + (self.collected - pots)
+    }
+
+    // prize of one ticket of a drawn round, zero when it did not win
+    pub fn ticket_prize(&self, ticket_pool: &TicketPool, pool_tickets: &[u8], ticket: u32) -> u64 {
+        match self.mode {
+            // every winning ticket gets the same share
+            LotteryMode::Raffle => match ticket_pool.is_winner(pool_tickets, ticket) {
+                true => self.collected / (self.winning_ticket_count as u64),
+                false => 0,
+            },
+            // the pot of the ticket's best tier is split evenly among the tier's tickets
+            LotteryMode::PickNumbers => match self.best_tier(match_count(ticket_pool.picks_of(pool_tickets, ticket), &self.winning_numbers)) {
+                Some(tier) => self.tier_pot(tier) / self.tier_winners[tier] as u64,
+                None => 0,
+            },
+        }
+    }
+}
+
+/// Bundle of the price schedule, `tickets` tickets bought together for `price`
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub struct PriceTier {
+    pub tickets: u32,
+    pub price: u64,
+}
+
+/// Payout of a pick-numbers round for tickets with at least `matches` drawn numbers,
+/// `percent` of the collected amount split evenly among the tier's tickets
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub struct PrizeTier {
+    pub matches: u8,
+    pub percent: u8,
+}
+
+// drawn tickets hash of the DrawRecord after a draw batch picked `drawn`:
+// sha256(previous || drawn tickets as u32 little-endian, in draw order), unchanged when it picked none
+pub fn drawn_tickets_hash(previous: &[u8; 32], drawn: &[u32]) -> [u8; 32] {
+    if drawn.is_empty() {
+        return *previous;
+    }
+    let mut hasher = Sha256::new();
+    hasher.update(previous);
+    for ticket in drawn.iter() {
+        hasher.update(ticket.to_le_bytes());
+    }
+    hasher.finalize().into()
+}
+
+// winning numbers of a pick-numbers round in draw order, `pick_count` distinct numbers
+// from 1..=pick_range, shared with the off-chain verifier
+pub fn drawn_numbers(pick_count: u8, pick_range: u8, seed: &[u8; 32]) -> Vec<u8> {
+    let mut stream = RandomnessStream::new(*seed);
+    let mut numbers: Vec<u8> = (1..=pick_range).collect();
+    stream.partial_shuffle(&mut numbers, pick_count as usize).to_vec()
+}
+
+// how many of a ticket's numbers were drawn
+pub fn match_count(picks: &[u8], winning_numbers: &[u8]) -> usize {
+    picks.iter().filter(|number| winning_numbers.contains(number)).count()
+}
+
+// royalty of a resale at `price`, list_ticket caps the price at MAX_TICKET_PRICE so it can't overflow
+pub fn royalty_of(price: u64, royalty_percent: u8) -> Result<u64> {
+    price.checked_mul(royalty_percent as u64)
+        .map(|amount| amount / 100)
+        .ok_or(error!(ErrCode::InvalidTicketPrice))
+}
+
+/// Ticket numbers of a lottery, kept out of the Lottery account so the round size is not
+/// bounded by the 10KB the program can allocate or grow an account by.
+/// The client creates the account with `TicketPool::space(ticket_amount, pick_count)` bytes
+/// and create_lottery claims it. The system program creates accounts of at most 10MiB, which holds
+/// 1,035,625 raffle tickets, see `max_capacity`. After the header come `capacity` little-endian u32
+/// slots, the first `remaining` hold the unsold tickets and the rest the sold ones, latest sale first.
+/// A zero slot stands for ticket `index + 1`, so a fresh pool needs no initialization.
+/// Pick-numbers rounds follow with `pick_count` bytes per ticket, the sorted numbers of
+/// ticket `t` at `(t - 1) * pick_count`, so they move with the ticket between owners.
+/// Then comes the winner bitmap written by the draw, ticket `t` is bit `(t - 1) % 8` of byte `(t - 1) / 8`,
+/// and last what each sold ticket paid into the prize account, 6 bytes little-endian at `6 * (t - 1)`,
+/// it never exceeds ticket_price, which create_lottery caps at MAX_TICKET_PRICE.
+/// Like the picks it moves with the ticket, so refunds pay back what the ticket actually paid.
+#[account(zero_copy)]
+pub struct TicketPool {
+    pub lottery: Pubkey,
+    pub capacity: u32,
+    pub remaining: u32,
+    pub pick_count: u32,
+}
+
+impl TicketPool {
+    pub const HEADER_SIZE: usize = 8 + std::mem::size_of::<TicketPool>();
+    // bytes of what a ticket paid
+    pub const PAID_SIZE: usize = 6;
+
+    pub fn space(capacity: u32, pick_count: u8) -> usize {
+        Self::HEADER_SIZE + (4 + pick_count as usize + Self::PAID_SIZE) * capacity as usize + (capacity as usize).div_ceil(8)
+    }
+
+    // most tickets of a pool no larger than MAX_TICKET_POOL_SPACE
+    pub fn max_capacity(pick_count: u8) -> u32 {
+        // 8 bytes of slot, picks and paid amount per bit of the winner bitmap
+        let bits_per_ticket = 8 * (4 + pick_count as usize + Self::PAID_SIZE) + 1;
+        let mut capacity = ((MAX_TICKET_POOL_SPACE - Self::HEADER_SIZE) * 8 / bits_per_ticket) as u32;
+        // the bitmap rounds up to whole bytes
+        while Self::space(capacity, pick_count) > MAX_TICKET_POOL_SPACE {
+            capacity -= 1;
+        }
+        capacity
+    }
+
+    // header and ticket slots of the pool, the slots can't be reached through AccountLoader::load_mut
+    pub fn load_mut<'a>(loader: &'a AccountLoader<TicketPool>) -> Result<(RefMut<'a, TicketPool>, RefMut<'a, [u8]>)> {
+        // checks owner, discriminator and writability
+        drop(loader.load_mut()?);
+        let data = loader.as_ref().try_borrow_mut_data()?;
+        let (header, tickets) = RefMut::map_split(data, |data| data.split_at_mut(Self::HEADER_SIZE));
+        Ok((RefMut::map(header, |header| bytemuck::from_bytes_mut(&mut header[8..])), tickets))
+    }
+
+    // read-only counterpart of load_mut
+    pub fn load<'a>(loader: &'a AccountLoader<TicketPool>) -> Result<(Ref<'a, TicketPool>, Ref<'a, [u8]>)> {
+        drop(loader.load()?);
+        let data = loader.as_ref().try_borrow_data()?;
+        let (header, tickets) = Ref::map_split(data, |data| data.split_at(Self::HEADER_SIZE));
+        Ok((Ref::map(header, |header| bytemuck::from_bytes(&header[8..])), tickets))
+    }
+
+    // `tickets` is the account data after the header
+    pub fn ticket_at(tickets: &[u8], index: usize) -> u32 {
+        match u32::from_le_bytes(tickets[index * 4..index * 4 + 4].try_into().unwrap()) {
+            0 => index as u32 + 1,
+            ticket => ticket,
+        }
+    }
+
+    fn set_ticket(tickets: &mut [u8], index: usize, ticket: u32) {
+        tickets[index * 4..index * 4 + 4].copy_from_slice(&ticket.to_le_bytes());
+    }
+
+    // removes a uniformly chosen unsold ticket and returns it, constant cost for any pool size:
+    // the ticket swaps places with the last unsold one and becomes the first sold slot
+    pub fn take_random_ticket(&mut self, tickets: &mut [u8], stream: &mut RandomnessStream) -> u32 {
+        let index = stream.uniform_below(self.remaining as u64) as usize;
+        let last = self.remaining as usize - 1;
+        let ticket = Self::ticket_at(tickets, index);
+        Self::swap_slots(tickets, index, last);
+        self.remaining -= 1;
+        ticket
+    }
+
+    // puts the cancelled ticket of a sold slot back with the unsold ones and returns it: it swaps
+    // places with the first sold slot, which then becomes the last unsold one, constant cost like
+    // take_random_ticket. The pool keeps no index of where tickets sit, callers pass the slot
+    pub fn return_ticket(&mut self, tickets: &mut [u8], slot: usize) -> u32 {
+        let first_sold = self.remaining as usize;
+        assert!(slot >= first_sold && slot < self.capacity as usize, "returned ticket was not sold");
+        let ticket = Self::ticket_at(tickets, slot);
+        Self::swap_slots(tickets, slot, first_sold);
+        self.remaining += 1;
+        self.set_picks(tickets, ticket, &vec![0u8; self.pick_count as usize]);
+        self.set_paid(tickets, ticket, 0);
+        ticket
+    }
+
+    fn swap_slots(tickets: &mut [u8], a: usize, b: usize) {
+        let ticket_a = Self::ticket_at(tickets, a);
+        let ticket_b = Self::ticket_at(tickets, b);
+        Self::set_ticket(tickets, a, ticket_b);
+        Self::set_ticket(tickets, b, ticket_a);
+    }
+
+    // slot of a sold ticket, scans the sold slots so it is meant for clients building cancel_tickets
+    pub fn find_slot(&self, tickets: &[u8], ticket: u32) -> Option<usize> {
+        (self.remaining as usize..self.capacity as usize).find(|&slot| Self::ticket_at(tickets, slot) == ticket)
+    }
+
+    // every sold ticket number in slot order, a copy of the pool meant for off-chain tools
+    pub fn sold_tickets(&self, tickets: &[u8]) -> Vec<u32> {
+        (self.remaining as usize..self.capacity as usize)
+            .map(|index| Self::ticket_at(tickets, index))
+            .collect()
+    }
+
+    // one raffle draw batch, a partial Fisher-Yates shuffle of the sold slots in place: for `i` in
+    // `drawn..drawn + count`, the i-th sold slot swaps with the `i + uniform_below(sold - i)`-th.
+    // The winners then sit in the first sold slots in draw order, the new ones are returned
+    pub fn draw_tickets(&self, tickets: &mut [u8], stream: &mut RandomnessStream, drawn: u32, count: u32) -> Vec<u32> {
+        let first_sold = self.remaining as usize;
+        let sold = (self.capacity - self.remaining) as u64;
+        (drawn..drawn + count).map(|i| {
+            let j = i as u64 + stream.uniform_below(sold - i as u64);
+            Self::swap_slots(tickets, first_sold + i as usize, first_sold + j as usize);
+            Self::ticket_at(tickets, first_sold + i as usize)
+        }).collect()
+    }
+
+    // puts the sold slots of a raffle drawn from `seed` back in their order before the draw,
+    // for off-chain verifiers: the swaps of draw_tickets only depend on the stream, so they
+    // are replayed and undone last to first
+    pub fn undo_draw(&self, tickets: &mut [u8], seed: &[u8; 32], drawn: u32) {
+        let first_sold = self.remaining as usize;
+        let sold = (self.capacity - self.remaining) as u64;
+        let mut stream = RandomnessStream::new(*seed);
+        let swaps: Vec<(usize, usize)> = (0..drawn as u64)
+            .map(|i| (i as usize, (i + stream.uniform_below(sold - i)) as usize))
+            .collect();
+        for &(i, j) in swaps.iter().rev() {
+            Self::swap_slots(tickets, first_sold + i, first_sold + j);
+        }
+    }
+
+    // numbers picked for a ticket, empty for raffles
+    pub fn picks_of<'a>(&self, tickets: &'a [u8], ticket: u32) -> &'a [u8] {
+        let start = 4 * self.capacity as usize + (ticket as usize - 1) * self.pick_count as usize;
+        &tickets[start..start + self.pick_count as usize]
+    }
+
+    pub fn set_picks(&self, tickets: &mut [u8], ticket: u32, picks: &[u8]) {
+        let start = 4 * self.capacity as usize + (ticket as usize - 1) * self.pick_count as usize;
+        tickets[start..start + self.pick_count as usize].copy_from_slice(picks);
+    }
+
+    fn winners_offset(&self) -> usize {
+        (4 + self.pick_count as usize) * self.capacity as usize
+    }
+
+    // whether the draw picked a ticket
+    pub fn is_winner(&self, tickets: &[u8], ticket: u32) -> bool {
+        let bit = ticket as usize - 1;
+        tickets[self.winners_offset() + bit / 8] & (1 << (bit % 8)) != 0
+    }
+
+    pub fn set_winner(&self, tickets: &mut [u8], ticket: u32, winner: bool) {
+        let bit = ticket as usize - 1;
+        match winner {
+            true => tickets[self.winners_offset() + bit / 8] |= 1 << (bit % 8),
+            false => tickets[self.winners_offset() + bit / 8] &= !(1 << (bit % 8)),
+        }
+    }
+
+    fn paid_offset(&self) -> usize {
+        self.winners_offset() + (self.capacity as usize).div_ceil(8)
+    }
+
+    // what a sold ticket paid into the prize account
+    pub fn paid_for(&self, tickets: &[u8], ticket: u32) -> u64 {
+        let start = self.paid_offset() + (ticket as usize - 1) * Self::PAID_SIZE;
+        let mut amount = [0u8; 8];
+        amount[..Self::PAID_SIZE].copy_from_slice(&tickets[start..start + Self::PAID_SIZE]);
+        u64::from_le_bytes(amount)
+    }
+
+    pub fn set_paid(&self, tickets: &mut [u8], ticket: u32, amount: u64) {
+        assert!(amount <= MAX_TICKET_PRICE, "paid amount does not fit the ticket pool");
+        let start = self.paid_offset() + (ticket as usize - 1) * Self::PAID_SIZE;
+        tickets[start..start + Self::PAID_SIZE].copy_from_slice(&amount.to_le_bytes()[..Self::PAID_SIZE]);
+    }
+
+    // what each ticket of one purchase paid into the prize account, refunded if it is cancelled
+    // or the round does not run, the remainder of the split goes to the first tickets
+    pub fn set_paid_shares(&self, tickets: &mut [u8], bought: &[u32], amount: u64) {
+        let count = bought.len() as u64;
+        for (index, &ticket) in bought.iter().enumerate() {
+            let share = amount / count + u64::from((index as u64) < amount % count);
+            self.set_paid(tickets, ticket, share);
+        }
+    }
+
+    // sold tickets hash of the DrawRecord after a draw batch went through `slots`:
+    // sha256(previous || `ticket (u32 little-endian) || picks` of every ticket in slot order)
+    pub fn sold_tickets_hash(&self, tickets: &[u8], slots: Range<usize>, previous: &[u8; 32]) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(previous);
+        for slot in slots {
+            let ticket = Self::ticket_at(tickets, slot);
+            hasher.update(ticket.to_le_bytes());
+            hasher.update(self.picks_of(tickets, ticket));
+        }
+        hasher.finalize().into()
+    }
+
+    // sold tickets hash of all sold slots, hashed DRAW_BATCH at a time like draw_batch does
+    pub fn sold_snapshot_hash(&self, tickets: &[u8]) -> [u8; 32] {
+        let first_sold = self.remaining as usize;
+        (first_sold..self.capacity as usize).step_by(DRAW_BATCH as usize).fold([0u8; 32], |hash, start| {
+            let end = self.capacity as usize;
+            self.sold_tickets_hash(tickets, start..end.min(start + DRAW_BATCH as usize), &hash)
+        })
+    }
+}
+
+/// Tickets of one buyer in one round, at `[b"tickets", lottery, buyer]`.
+/// Created by the buyer's first purchase or received ticket and grown by later ones, so a purchase no
+/// longer scans or grows a list of all buyers. Purchases still write the Lottery (sale counters,
+/// entropy accumulator), the TicketPool (ticket assignment) and the prize token account, and claims
+/// write the prize token account, so the purchases and the claims of one round are serialized.
+#[account]
+pub struct TicketReceipt {
+    pub lottery: Pubkey,
+    pub owner: Pubkey,
+    pub tickets: Vec<u32>,
+    // tickets held by a TicketListing of this owner
+    pub listed_tickets: u32,
+    // tickets this owner turned into tokens with mint_ticket
+    pub minted_tickets: u32,
+    // prize of a drawn round or refund of an unresolved one
+    pub claimed_amount: u64,
+    pub claimed: bool,
+    pub refunded: bool,
+    bump: u8,
+}
+
+impl TicketReceipt {
+    pub fn space(ticket_count: usize) -> usize {
+        8 + 32 + 32 + (4 + 4 * ticket_count) + 4 + 4 + 8 + 1 + 1 + 1
+    }
+
+    // tickets on the receipt, in listings and minted, what max_tickets_per_buyer and allowances apply to
+    pub fn held_tickets(&self) -> usize {
+        self.tickets.len() + self.listed_tickets as usize + self.minted_tickets as usize
+    }
+
+    // fills in a receipt just created by init_if_needed, no-op for an existing one
+    fn open(&mut self, lottery: Pubkey, owner: Pubkey, bump: u8) {
+        if self.owner == Pubkey::default() {
+            self.lottery = lottery;
+            self.owner = owner;
+            self.bump = bump;
+        }
+    }
+}
+
+/// A ticket for sale at `[b"listing", lottery, ticket_number]`, the listing holds the ticket
+/// until it is bought or cancelled. Settled in the lottery's prize token.
+#[account]
+pub struct TicketListing {
+    pub lottery: Pubkey,
+    pub seller: Pubkey,
+    pub ticket_number: u32,
+    pub price: u64,
+    bump: u8,
+}
+
+/// Referral account of a referrer for one prize mint, at `[b"referrer", referrer, mint]`.
+/// Its share of the fees is held by the token account at `[b"referral-vault", referrer_stats]`.
+#[account]
+pub struct ReferrerStats {
+    pub referrer: Pubkey,
+    pub mint: Pubkey,
+    pub vault: Pubkey,
+    // lifetime amount charged and tickets sold to referred buyers
+    pub referred_volume: u64,
+    pub referred_tickets: u64,
+    pub pending_rewards: u64,
+    pub claimed_rewards: u64,
+    bump: u8,
+    vault_bump: u8,
+}
+
+#[event]
+pub struct TicketTransferred {
+    pub lottery: Pubkey,
+    pub ticket_number: u32,
+    pub from: Pubkey,
+    pub to: Pubkey,
+}
+
+// instruction arguments of buy_tickets and buy_tickets_for, `owner` is the wallet getting the tickets
+struct TicketOrder {
+    owner: Pubkey,
+    ticket_amount: u64,
+    client_seed: Option<[u8; 32]>,
+    picks: Vec<u8>,
+    allowlist_proof: Vec<[u8; 32]>,
+    allowance: u32,
+}
+
+// shared by buy_tickets and buy_tickets_for, the sale signer pays for the tickets added to the receipt
+// of `order.owner`, returns the new ticket numbers
+fn sell_tickets<'info>(
+    sale: &mut TicketSale<'info>,
+    receipt: &mut Account<'info, TicketReceipt>,
+    receipt_bump: u8,
+    system_program: &Program<'info, System>,
+    order: TicketOrder
+) -> Result<Vec<u32>> {
+    let TicketOrder { owner, ticket_amount, client_seed, picks, allowlist_proof, allowance } = order;
+    let lottery = &mut sale.lottery;
+    let payer = &sale.signer;
+    let payer_token = &sale.creator_token;
+    let token_program = &sale.token_program;
+    let app_stats = &sale.app_stats;
+    lottery.check_sales_open(&sale.clock)?;
+
+    // check available tickets
+    if ticket_amount > MAX_PURCHASE_TICKETS {
+        return err!(ErrCode::PurchaseTooLarge);
+    }
+    let (mut ticket_pool, mut pool_tickets) = TicketPool::load_mut(&sale.ticket_pool)?;
+    if ticket_amount == 0 || ticket_amount > ticket_pool.remaining as u64 {
+        return err!(ErrCode::InvalidArgus);
+    }
+    if picks.len() != ticket_amount as usize * lottery.pick_count as usize {
+        return err!(ErrCode::InvalidPicks);
+    }
+
+    // check if the owner would go over the maximum number of tickets, or may not hold them at all
+    receipt.open(lottery.key(), owner, receipt_bump);
+    lottery.check_ticket_cap(receipt.held_tickets(), ticket_amount as usize)?;
+    lottery.check_entry(&owner, sale.gate_token.as_deref(), &allowlist_proof, allowance, receipt.held_tickets() + ticket_amount as usize)?;
+
+    let total_amount = lottery.price_of(ticket_amount)?;
+    let fee_amount: u64 = total_amount.checked_mul(app_stats.fee_percent as u64).ok_or(error!(ErrCode::InvalidArgus))? / 100;
+    let real_amount:u64 = total_amount - fee_amount;
+
+    // the referrer's share of the fee accrues in its vault until claim_referral_rewards
+    let mut referral_amount: u64 = 0;
+    if let Some((referrer_stats, referral_vault)) = sale.referrer_stats.as_deref_mut().zip(sale.referral_vault.as_deref()) {
+        if referrer_stats.mint != lottery.prize_token || referral_vault.key() != referrer_stats.vault {
+            return err!(ErrCode::InvalidReferrer);
+        }
+        // nobody can refer their own tickets
+        if referrer_stats.referrer == payer.key() || referrer_stats.referrer == owner {
+            return err!(ErrCode::InvalidReferrer);
+        }
+        referral_amount = (fee_amount * (app_stats.referral_percent as u64)) / 100;
+        referrer_stats.pending_rewards += referral_amount;
+        referrer_stats.referred_volume += total_amount;
+        referrer_stats.referred_tickets += ticket_amount;
+
+        if referral_amount > 0 {
+            let cpi_accounts = Transfer {
+                from: payer_token.to_account_info(),
+                to: referral_vault.to_account_info(),
+                authority: payer.to_account_info(),
+            };
+            let cpi_program = token_program.to_account_info();
+            token::transfer(CpiContext::new(cpi_program, cpi_accounts), referral_amount)?;
+        }
+    }
+
+    // transfer the rest of the fee to the fee account
+    if fee_amount > referral_amount {
+        let cpi_accounts = Transfer {
+            from: payer_token.to_account_info(),
+            to: sale.fee_token.to_account_info(),
+            authority: payer.to_account_info(),
+        };
+        let cpi_program = token_program.to_account_info();
+        let cpi_ctx: CpiContext<Transfer> = CpiContext::new(cpi_program, cpi_accounts);
+        token::transfer(cpi_ctx, fee_amount - referral_amount)?;
+    }
+
+    // transfer tokens from buyer to prize account
+    let cpi_accounts = Transfer {
+        from: payer_token.to_account_info(),
+        to: sale.prize.to_account_info(),
+        authority: payer.to_account_info(),
+    };
+    let cpi_program = token_program.to_account_info();
+    let cpi_ctx: CpiContext<Transfer> = CpiContext::new(cpi_program, cpi_accounts);
+    token::transfer(cpi_ctx, real_amount)?;
+
+    resize_receipt(
+        &receipt.to_account_info(),
+        &payer.to_account_info(),
+        &system_program.to_account_info(),
+        receipt.held_tickets() + ticket_amount as usize
+    )?;
+
+    // ticket numbers only label the tickets, the draw picks the winners with its own randomness
+    // whatever numbers they hold, so the assignment is seeded from the slot alone and the nonce
+    // keeps the purchases of one slot apart
+    let mut stream = RandomnessStream::from_seed_and_nonce(sale.clock.slot, ticket_pool.remaining as u64);
+    for _ in 0..ticket_amount {
+        let ticket_to_add = ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream);
+        receipt.tickets.push(ticket_to_add);
+    }
+
+    // numbers of pick-numbers tickets are kept sorted in the pool, by ticket number
+    let new_tickets = receipt.tickets[receipt.tickets.len() - ticket_amount as usize..].to_vec();
+    if lottery.mode == LotteryMode::PickNumbers {
+        let pick_count = lottery.pick_count as usize;
+        for (&ticket, ticket_picks) in new_tickets.iter().zip(picks.chunks(pick_count)) {
+            let mut ticket_picks = ticket_picks.to_vec();
+            ticket_picks.sort();
+            if ticket_picks[0] == 0
+                || ticket_picks[pick_count - 1] > lottery.pick_range
+                || ticket_picks.windows(2).any(|pair| pair[0] == pair[1]) {
+                return err!(ErrCode::InvalidPicks);
+            }
+            ticket_pool.set_picks(&mut pool_tickets, ticket, &ticket_picks);
+        }
+    }
+    ticket_pool.set_paid_shares(&mut pool_tickets, &new_tickets, real_amount);
+    lottery.tickets_sold += ticket_amount as u32;
+    lottery.collected += real_amount;
+
+    if let Some(client_seed) = client_seed {
+        lottery.entropy_accumulator = fold_entropy(
+            &lottery.entropy_accumulator,
+            &payer.key().to_bytes(),
+            &client_seed
+        );
+    }
+    Ok(new_tickets)
+}
+
+#[event]
+pub struct TicketsGifted {
+    pub lottery: Pubkey,
+    pub payer: Pubkey,
+    pub beneficiary: Pubkey,
+    pub tickets: Vec<u32>,
+    pub memo: Option<String>,
+}
+
+// grows a receipt to hold `ticket_count` tickets, the payer covers the extra rent.
+// Callers size it from held_tickets() so listed tickets still fit once cancel_listing returns them
+fn resize_receipt<'info>(
+    receipt: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    system_program: &AccountInfo<'info>,
+    ticket_count: usize
+) -> Result<()> {
+    let space = TicketReceipt::space(ticket_count);
+    if space <= receipt.data_len() {
+        return Ok(());
+    }
+
+    let rent = Rent::get()?.minimum_balance(space).saturating_sub(receipt.lamports());
+    if rent > 0 {
+        let cpi_accounts = system_program::Transfer {
+            from: payer.clone(),
+            to: receipt.clone(),
+        };
+        system_program::transfer(CpiContext::new(system_program.clone(), cpi_accounts), rent)?;
+    }
+    receipt.realloc(space, false)?;
+    Ok(())
+}
+
+/// Audit trail of a draw, one per lottery at `[b"draw-record", lottery]`, it also keeps the
+/// progress of a draw running over several transactions.
+/// `sold_tickets_hash` chains the sold slots of the pool in slot order, DRAW_BATCH at a time, see
+/// `TicketPool::sold_snapshot_hash`. Check `slot_hash` against the ledger for slot-hash and
+/// commit-reveal rounds, then seed a `RandomnessStream` with `stream_seed()`.
+/// Raffles run one partial Fisher-Yates shuffle over the sold slots in place, drawing half of them,
+/// so the winners end up in the first sold slots in draw order and `TicketPool::undo_draw` gives back
+/// the hashed order. `drawn_tickets_hash` chains the tickets of each draw batch, see `drawn_tickets_hash`.
+/// Pick-numbers rounds shuffle 1..=pick_range instead, picking pick_count numbers that must match
+/// `drawn_numbers` in order, and `drawn_tickets_hash` chains the matching tickets of each batch of
+/// sold slots. Either way the drawn tickets are the ones marked in the pool's winner bitmap,
+/// and `Lottery::start_draw` and `draw_batch` replay the whole draw.
+#[account]
+#[derive(Default)]
+pub struct DrawRecord {
+    pub lottery: Pubkey,
+    pub algorithm_version: u8,
+    // entropy sources
+    pub randomness_source: RandomnessSource,
+    pub oracle_program: Pubkey,
+    pub randomness_account: Pubkey,
+    pub secret_commitment: [u8; 32],
+    pub target_slot: u64,
+    // the revealed secret, the oracle randomness or the target slot hash
+    pub randomness: [u8; 32],
+    // hash of the first slot at or after target_slot, zero in oracle rounds
+    pub slot_hash: [u8; 32],
+    pub entropy_accumulator: [u8; 32],
+    pub sold_tickets_hash: [u8; 32],
+    pub sold_ticket_count: u32,
+    pub drawn_ticket_count: u32,
+    pub drawn_tickets_hash: [u8; 32],
+    pub drawn_numbers: Vec<u8>,
+    pub drawn_at: i64,
+    pub status: LotteryStatus,
+    // draw progress, sold slots hashed and values taken from the draw RandomnessStream so far
+    pub hashed_ticket_count: u32,
+    pub stream_position: u64,
+    bump: u8,
+}
+
+impl DrawRecord {
+    pub const SPACE: usize = 8 + 32 + 1 + 1 + 32 * 8 + 8 + 4 + 4 + (4 + MAX_PICK_COUNT as usize) + 8 + 1 + 4 + 8 + 1;
+
+    // the entropy of the draw, written before it runs
+    fn record_inputs(&mut self, lottery: &Account<Lottery>, randomness: &[u8; 32], slot_hash: &[u8; 32], now: i64, bump: u8) {
+        self.lottery = lottery.key();
+        self.algorithm_version = DRAW_ALGORITHM_VERSION;
+        self.randomness_source = lottery.randomness_source.clone();
+        self.oracle_program = lottery.oracle_program;
+        self.randomness_account = lottery.randomness_account;
+        self.secret_commitment = lottery.secret_commitment;
+        self.target_slot = lottery.target_slot;
+        self.randomness = *randomness;
+        self.slot_hash = *slot_hash;
+        self.entropy_accumulator = lottery.entropy_accumulator;
+        self.sold_ticket_count = lottery.tickets_sold;
+        self.drawn_at = now;
+        self.bump = bump;
+    }
+
+    // a round past its reveal deadline turns unresolved without randomness, the record shows when
+    fn record_missed_deadline(&mut self, lottery: &Account<Lottery>, now: i64, bump: u8) {
+        self.record_inputs(lottery, &[0u8; 32], &[0u8; 32], now, bump);
+        self.record_outcome(lottery);
+    }
+
+    // the drawn numbers and the status of the round, written when the draw starts and ends
+    fn record_outcome(&mut self, lottery: &Lottery) {
+        self.drawn_numbers = lottery.winning_numbers.clone();
+        self.status = lottery.status.clone();
+    }
+
+    // the draw RandomnessStream where the last batch left it
+    fn stream(&self) -> RandomnessStream {
+        RandomnessStream::resume(self.stream_seed(), self.stream_position)
+    }
+
+    // the inputs of the draw without its outcome, for verifiers replaying it
+    pub fn replay_inputs(&self) -> DrawRecord {
+        DrawRecord {
+            lottery: self.lottery,
+            algorithm_version: self.algorithm_version,
+            randomness_source: self.randomness_source.clone(),
+            oracle_program: self.oracle_program,
+            randomness_account: self.randomness_account,
+            secret_commitment: self.secret_commitment,
+            target_slot: self.target_slot,
+            randomness: self.randomness,
+            slot_hash: self.slot_hash,
+            entropy_accumulator: self.entropy_accumulator,
+            sold_ticket_count: self.sold_ticket_count,
+            drawn_at: self.drawn_at,
+            bump: self.bump,
+            ..DrawRecord::default()
+        }
+    }
+
+    // seed of the draw RandomnessStream, commit-reveal rounds first mix the secret with the slot hash
+    pub fn stream_seed(&self) -> [u8; 32] {
+        match self.randomness_source {
+            RandomnessSource::CommitReveal => mix_entropy(&mix_entropy(&self.randomness, &self.slot_hash), &self.entropy_accumulator),
+            _ => mix_entropy(&self.randomness, &self.entropy_accumulator),
+        }
+    }
+}
+
+// hash of the first produced slot at or after target_slot, read from the SlotHashes sysvar data
+// (u64 entry count, then (slot: u64, hash: [u8; 32]) entries, newest first)
+pub fn target_slot_hash(data: &[u8], target_slot: u64, current_slot: u64) -> Result<[u8; 32]> {
+    if current_slot <= target_slot {
+        return err!(ErrCode::TargetSlotNotReached);
+    }
+
+    let len = u64::from_le_bytes(data[..8].try_into().unwrap()) as usize;
+    let mut found: Option<[u8; 32]> = None;
+    let mut oldest_slot = u64::MAX;
+    for i in 0..len {
+        let offset = 8 + i * 40;
+        let slot = u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        if slot < target_slot {
+            oldest_slot = slot;
+            break;
+        }
+        // entries are newest first, the last one at or after the target is the first produced
+        oldest_slot = slot;
+        found = Some(data[offset + 8..offset + 40].try_into().unwrap());
+    }
+
+    // the target slot itself must still be in the window, otherwise a later slot could be picked
+    if oldest_slot > target_slot {
+        return err!(ErrCode::SlotHashExpired);
+    }
+    found.ok_or(error!(ErrCode::TargetSlotNotReached))
+}
+
+/// Result account written by the VRF oracle program.
+/// Any oracle can be plugged in as long as its result account starts with this layout:
+/// [8 byte discriminator][requester: Pubkey][fulfilled: bool][randomness: [u8; 32]]
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct VrfResult {
+    pub requester: Pubkey,
+    pub fulfilled: bool,
+    pub randomness: [u8; 32],
+}
+
+impl VrfResult {
+    pub fn load(account: &AccountInfo, oracle_program: &Pubkey) -> Result<VrfResult> {
+        if account.owner != oracle_program {
+            return err!(ErrCode::InvalidRandomnessAccount);
+        }
+        let data = account.try_borrow_data()?;
+        if data.len() < 8 {
+            return err!(ErrCode::InvalidRandomnessAccount);
+        }
+        let mut payload: &[u8] = &data[8..];
+        VrfResult::deserialize(&mut payload).map_err(|_| error!(ErrCode::InvalidRandomnessAccount))
+    }
+}
+
+/// Accounts of a ticket sale shared by BuyTickets and BuyTicketsFor, which add the receipt
+/// of the wallet getting the tickets
+#[derive(Accounts)]
+pub struct TicketSale<'info> {
+    #[account(mut, has_one = ticket_pool)]
+    pub lottery: Account<'info, Lottery>,
+
+    #[account(mut)]
+    pub ticket_pool: AccountLoader<'info, TicketPool>,
+
+    // pays for the tickets and for growing the receipt
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(mut, seeds = [b"proceeds", lottery.key().as_ref()], bump = lottery.proceeds_bump)]
+    pub proceeds: SystemAccount<'info>,
+
+    #[account(
+        mut, 
+        seeds = [b"app-stats", owner.key().as_ref()], 
+        bump = app_stats.bump, 
+        constraint = fee_account.key() == app_stats.fee_account
+    )]
+    pub app_stats: Account<'info, AppStats>,
+
+    #[account(
+        mut,
+        constraint = creator_token.mint == lottery.prize_token
+    )]
+    pub creator_token: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"prize", lottery.key().as_ref()],
+        bump = lottery.prize_bump
+    )]
+    pub prize: Account<'info, TokenAccount>,
+    // #[account(address = token::ID)]
+    pub token_program: Program<'info, Token>,
+
+    /// CHECK: don't read and write this account
+    #[account(mut)]
+    pub fee_account: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        constraint = fee_token.mint == lottery.prize_token,
+        constraint = fee_token.owner == app_stats.fee_account
+    )]
+    pub fee_token: Box<Account<'info, TokenAccount>>,
+
+    // optional referral, checked against the lottery mint in sell_tickets
+    #[account(mut)]
+    pub referrer_stats: Option<Box<Account<'info, ReferrerStats>>>,
+
+    #[account(mut)]
+    pub referral_vault: Option<Box<Account<'info, TokenAccount>>>,
+
+    // held by the wallet getting the tickets, required when lottery.gate_mint is set,
+    // the owner is checked in sell_tickets
+    #[account(constraint = gate_token.mint == lottery.gate_mint)]
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// CHECK: the lottery creator, whose app stats set the fee and the referral share
+    #[account(constraint = owner.key() == lottery.creator)]
+    pub owner: AccountInfo<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct BuyTickets<'info> {
+    pub sale: TicketSale<'info>,
+
+    // grown in buy_tickets as tickets are added
+    #[account(
+        init_if_needed,
+        payer = sale.signer,
+        space = TicketReceipt::space(0),
+        seeds = [b"tickets", sale.lottery.key().as_ref(), sale.signer.key().as_ref()],
+        bump
+    )]
+    pub receipt: Account<'info, TicketReceipt>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(beneficiary: Pubkey)]
+pub struct BuyTicketsFor<'info> {
+    pub sale: TicketSale<'info>,
+
+    // receipt of the beneficiary, the signer pays for it and the tickets
+    #[account(
+        init_if_needed,
+        payer = sale.signer,
+        space = TicketReceipt::space(0),
+        seeds = [b"tickets", sale.lottery.key().as_ref(), beneficiary.as_ref()],
+        bump
+    )]
+    pub receipt: Account<'info, TicketReceipt>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelTickets<'info> {
+    #[account(mut, has_one = ticket_pool, constraint = lottery.prize_token == user_token.mint)]
+    pub lottery: Account<'info, Lottery>,
+
+    #[account(mut)]
+    pub ticket_pool: AccountLoader<'info, TicketPool>,
+
+    #[account(
+        mut,
+        seeds = [b"tickets", lottery.key().as_ref(), signer.key().as_ref()],
+        bump = receipt.bump
+    )]
+    pub receipt: Account<'info, TicketReceipt>,
+
+    pub signer: Signer<'info>,
+
+    #[account(mut, constraint = user_token.owner == signer.key())]
+    pub user_token: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        seeds = [b"prize", lottery.key().as_ref()],
+        bump = lottery.prize_bump
+    )]
+    pub prize: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_token.mint == lottery.prize_token,
+        constraint = fee_token.owner == app_stats.fee_account
+    )]
+    pub fee_token: Account<'info, TokenAccount>,
+
+    #[account(seeds = [b"app-stats", owner.key().as_ref()], bump = app_stats.bump)]
+    pub app_stats: Account<'info, AppStats>,
+
+    /// CHECK: the lottery creator, whose app stats name the fee account
+    #[account(constraint = owner.key() == lottery.creator)]
+    pub owner: AccountInfo<'info>,
+    pub token_program: Program<'info, Token>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+#[instruction(ticket_number: u32, new_owner: Pubkey)]
+pub struct TransferTicket<'info> {
+    pub lottery: Account<'info, Lottery>,
+
+    #[account(
+        mut,
+        seeds = [b"tickets", lottery.key().as_ref(), signer.key().as_ref()],
+        bump = receipt.bump
+    )]
+    pub receipt: Account<'info, TicketReceipt>,
+
+    // grown in transfer_ticket as the ticket is added
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = TicketReceipt::space(0),
+        seeds = [b"tickets", lottery.key().as_ref(), new_owner.as_ref()],
+        bump
+    )]
+    pub new_owner_receipt: Account<'info, TicketReceipt>,
+
+    // held by the new owner, required when lottery.gate_mint is set
+    #[account(constraint = gate_token.mint == lottery.gate_mint)]
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(ticket_number: u32)]
+pub struct ListTicket<'info> {
+    pub lottery: Account<'info, Lottery>,
+
+    #[account(
+        mut,
+        seeds = [b"tickets", lottery.key().as_ref(), seller.key().as_ref()],
+        bump = receipt.bump
+    )]
+    pub receipt: Account<'info, TicketReceipt>,
+
+    #[account(
+        init,
+        payer = seller,
+        space = 8 + 32 + 32 + 4 + 8 + 1,
+        seeds = [b"listing", lottery.key().as_ref(), ticket_number.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub listing: Account<'info, TicketListing>,
+
+    #[account(mut)]
+    pub seller: Signer<'info>,
+    pub clock: Sysvar<'info, Clock>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CancelListing<'info> {
+    pub lottery: Account<'info, Lottery>,
+
+    #[account(
+        mut,
+        close = seller,
+        has_one = seller,
+        seeds = [b"listing", lottery.key().as_ref(), listing.ticket_number.to_le_bytes().as_ref()],
+        bump = listing.bump
+    )]
+    pub listing: Account<'info, TicketListing>,
+
+    #[account(
+        mut,
+        seeds = [b"tickets", lottery.key().as_ref(), seller.key().as_ref()],
+        bump = receipt.bump
+    )]
+    pub receipt: Account<'info, TicketReceipt>,
+
+    /// CHECK: listing.seller, receives the listing rent
+    #[account(mut)]
+    pub seller: AccountInfo<'info>,
+
+    // pays for growing the receipt if it is too small for the returned ticket
+    #[account(mut)]
+    pub signer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BuyListedTicket<'info> {
+    pub lottery: Account<'info, Lottery>,
+
+    #[account(
+        mut,
+        close = seller,
+        has_one = seller,
+        seeds = [b"listing", lottery.key().as_ref(), listing.ticket_number.to_le_bytes().as_ref()],
+        bump = listing.bump
+    )]
+    pub listing: Account<'info, TicketListing>,
+
+    #[account(
+        mut,
+        seeds = [b"tickets", lottery.key().as_ref(), seller.key().as_ref()],
+        bump = seller_receipt.bump
+    )]
+    pub seller_receipt: Account<'info, TicketReceipt>,
+
+    // grown in buy_listed_ticket as the ticket is added
+    #[account(
+        init_if_needed,
+        payer = signer,
+        space = TicketReceipt::space(0),
+        seeds = [b"tickets", lottery.key().as_ref(), signer.key().as_ref()],
+        bump
+    )]
+    pub buyer_receipt: Account<'info, TicketReceipt>,
+
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        mut,
+        constraint = buyer_token.mint == lottery.prize_token
+    )]
+    pub buyer_token: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = seller_token.mint == lottery.prize_token,
+        constraint = seller_token.owner == seller.key()
+    )]
+    pub seller_token: Account<'info, TokenAccount>,
+
+    #[account(
+        mut,
+        constraint = fee_token.mint == lottery.prize_token,
+        constraint = fee_token.owner == app_stats.fee_account
+    )]
+    pub fee_token: Account<'info, TokenAccount>,
+
+    // held by the signer, required when lottery.gate_mint is set
+    #[account(constraint = gate_token.mint == lottery.gate_mint)]
+    pub gate_token: Option<Box<Account<'info, TokenAccount>>>,
+
+    /// CHECK: listing.seller, receives the listing rent
+    #[account(mut)]
+    pub seller: AccountInfo<'info>,
+
+    #[account(seeds = [b"app-stats", owner.key().as_ref()], bump = app_stats.bump)]
+    pub app_stats: Account<'info, AppStats>,
+
+    /// CHECK: the lottery creator, whose app stats set the royalty and the fee account
+    #[account(constraint = owner.key() == lottery.creator)]
+    pub owner: AccountInfo<'info>,
+
+    pub clock: Sysvar<'info, Clock>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(ticket_number: u32)]
+pub struct MintTicket<'info> {
+    pub lottery: Account<'info, Lottery>,
 
-    #[account(init, payer = signer, space = 8 + 3000)]
-    pub lottery: Box<Account<'info, Lottery>>,
+    #[account(
+        mut,
+        seeds = [b"tickets", lottery.key().as_ref(), user.key().as_ref()],
+        bump = receipt.bump
+    )]
+    pub receipt: Account<'info, TicketReceipt>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
 
     #[account(
         init,
-        seeds = [b"prize", lottery.key().as_ref()],
+        payer = user,
+        seeds = [b"ticket-mint", lottery.key().as_ref(), ticket_number.to_le_bytes().as_ref()],
         bump,
-        payer = signer,
-        owner = token_program.key(),
-        rent_exempt = enforce,
-        token::mint = mint,
-        token::authority = prize
+        mint::decimals = 0,
+        mint::authority = ticket_mint
     )]
-    pub prize: Account<'info, TokenAccount>,
+    pub ticket_mint: Account<'info, Mint>,
+
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = ticket_mint,
+        associated_token::authority = user
+    )]
+    pub ticket_token: Account<'info, TokenAccount>,
 
-    #[account(seeds = [b"proceeds", lottery.key().as_ref()], bump)]
-    pub proceeds: SystemAccount<'info>,
-    pub clock: Sysvar<'info, Clock>,
-    pub mint: Account<'info, Mint>,
     pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
-    pub app_stats: Account<'info, AppStats>,
-
-    /// CHECK: don't read and write this account
-    pub admin_account: AccountInfo<'info>,
-}
-
-#[account]
-pub struct Lottery {
-    pub creator: Pubkey,
-    pub start: i64,
-    pub end: i64,
-    pub winners: Vec<Winner>,
-    pub ticket_price: u64,
-    pub ticket_amount: u8,
-    pub prize_token: Pubkey,
-    pub claimed_amount: u64,
-    pub buyers: Vec<Buyer>,
-    pub left_tickets: Vec<u8>,
-    pub prize_bump: u8,
-    pub proceeds_bump: u8,
-    pub collected: u64,
-    pub max_tickets_per_buyer: u8,
-    pub status: LotteryStatus,
 }
 
-#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
-pub struct Buyer {
-    pub participant: Pubkey,
-    pub tickets: Vec<u8>,
-}
+#[derive(Accounts)]
+pub struct UpdateMaxTicketsPerBuyer<'info> {
+    #[account(mut, has_one = creator)]
+    pub lottery: Account<'info, Lottery>,
 
-#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
-pub struct Winner {
-    pub participant: Pubkey,
-    pub claimed: bool,
-    pub claimed_amount: u64,
+    pub creator: Signer<'info>,
 }
 
 #[derive(Accounts)]
-pub struct BuyTickets<'info> {
-    #[account(mut)]
+pub struct RevealWinner<'info> {
+    #[account(mut, has_one = ticket_pool)]
     pub lottery: Account<'info, Lottery>,
 
+    // the draw marks the winners in the pool
     #[account(mut)]
-    pub signer: Signer<'info>,
+    pub ticket_pool: AccountLoader<'info, TicketPool>,
 
-    #[account(mut, seeds = [b"proceeds", lottery.key().as_ref()], bump = lottery.proceeds_bump)]
-    pub proceeds: SystemAccount<'info>,
-
-    #[account(
-        mut, 
-        seeds = [b"app-stats", owner.key().as_ref()], 
-        bump = app_stats.bump, 
-        constraint = fee_account.key() == app_stats.fee_account
-    )]
-    pub app_stats: Account<'info, AppStats>,
+    /// CHECK: address is the SlotHashes sysvar, parsed in target_slot_hash
+    #[account(address = slot_hashes::ID)]
+    pub slot_hashes: AccountInfo<'info>,
 
-    #[account(
-        mut,
-        constraint = creator_token.mint == lottery.prize_token
-    )]
-    pub creator_token: Account<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
 
     #[account(
-        mut,
-        seeds = [b"prize", lottery.key().as_ref()],
-        bump = lottery.prize_bump
+        init,
+        payer = payer,
+        space = DrawRecord::SPACE,
+        seeds = [b"draw-record", lottery.key().as_ref()],
+        bump
     )]
-    pub prize: Account<'info, TokenAccount>,
-    // #[account(address = token::ID)]
-    pub token_program: Program<'info, Token>,
+    pub draw_record: Account<'info, DrawRecord>,
+    pub clock: Sysvar<'info, Clock>,
+    pub system_program: Program<'info, System>,
+}
 
-    /// CHECK: don't read and write this account
+#[derive(Accounts)]
+pub struct RecommitTargetSlot<'info> {
     #[account(mut)]
-    pub fee_account: AccountInfo<'info>,
+    pub lottery: Account<'info, Lottery>,
 
-    /// CHECK: don't read and write this account
-    pub owner: AccountInfo<'info>,
+    /// CHECK: address is the SlotHashes sysvar, parsed in target_slot_hash
+    #[account(address = slot_hashes::ID)]
+    pub slot_hashes: AccountInfo<'info>,
     pub clock: Sysvar<'info, Clock>,
-    pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
-pub struct RevealWinner<'info> {
+pub struct SaveSlotHash<'info> {
     #[account(mut)]
     pub lottery: Account<'info, Lottery>,
+
+    /// CHECK: address is the SlotHashes sysvar, parsed in target_slot_hash
+    #[account(address = slot_hashes::ID)]
+    pub slot_hashes: AccountInfo<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct RequestRandomness<'info> {
+    #[account(mut, has_one = creator)]
+    pub lottery: Account<'info, Lottery>,
+
+    pub creator: Signer<'info>,
+
+    /// CHECK: owner and layout are validated against lottery.oracle_program in VrfResult::load
+    pub randomness_account: AccountInfo<'info>,
+    pub clock: Sysvar<'info, Clock>,
+}
+
+#[derive(Accounts)]
+pub struct ConsumeRandomness<'info> {
+    #[account(mut, has_one = ticket_pool)]
+    pub lottery: Account<'info, Lottery>,
+
+    // the draw marks the winners in the pool
+    #[account(mut)]
+    pub ticket_pool: AccountLoader<'info, TicketPool>,
+
+    /// CHECK: owner and layout are validated against lottery.oracle_program in VrfResult::load
+    #[account(address = lottery.randomness_account)]
+    pub randomness_account: AccountInfo<'info>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = DrawRecord::SPACE,
+        seeds = [b"draw-record", lottery.key().as_ref()],
+        bump
+    )]
+    pub draw_record: Account<'info, DrawRecord>,
     pub clock: Sysvar<'info, Clock>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct ContinueDraw<'info> {
+    #[account(mut, has_one = ticket_pool)]
+    pub lottery: Account<'info, Lottery>,
+
+    // the draw marks the winners in the pool
+    #[account(mut)]
+    pub ticket_pool: AccountLoader<'info, TicketPool>,
+
+    #[account(
+        mut,
+        seeds = [b"draw-record", lottery.key().as_ref()],
+        bump = draw_record.bump
+    )]
+    pub draw_record: Account<'info, DrawRecord>,
+}
+
 #[derive(Accounts)]
 pub struct UpdateLotteryStatus<'info> {
     #[account(mut)]
     pub lottery: Account<'info, Lottery>,
+
+    #[account(mut)]
+    pub payer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = payer,
+        space = DrawRecord::SPACE,
+        seeds = [b"draw-record", lottery.key().as_ref()],
+        bump
+    )]
+    pub draw_record: Account<'info, DrawRecord>,
     pub clock: Sysvar<'info, Clock>,
     pub system_program: Program<'info, System>,
 }
 
 #[derive(Accounts)]
 pub struct ClaimPrize<'info> {
-    #[account(mut, constraint = lottery.prize_token == mint.key())]
+    #[account(constraint = lottery.prize_token == mint.key(), has_one = ticket_pool)]
     pub lottery: Account<'info, Lottery>,
 
+    pub ticket_pool: AccountLoader<'info, TicketPool>,
+
+    #[account(
+        mut,
+        seeds = [b"tickets", lottery.key().as_ref(), user.key().as_ref()],
+        bump = receipt.bump
+    )]
+    pub receipt: Account<'info, TicketReceipt>,
+
     #[account(mut)]
     pub user: Signer<'info>,
 
@@ -552,21 +2751,150 @@ pub struct ClaimPrize<'info> {
     #[account(mut)]
     pub user_token: AccountInfo<'info>,
 
+    #[account(
+        mut,
+        seeds = [b"prize", lottery.key().as_ref()],
+        bump = lottery.prize_bump
+    )]
+    pub prize: Account<'info, TokenAccount>,
+    pub mint: Account<'info, Mint>,
+
+    pub token_program: Program<'info, Token>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(ticket_number: u32)]
+pub struct ClaimTicketPrize<'info> {
+    #[account(constraint = lottery.prize_token == mint.key(), has_one = ticket_pool)]
+    pub lottery: Account<'info, Lottery>,
+
+    pub ticket_pool: AccountLoader<'info, TicketPool>,
+
+    #[account(
+        mut,
+        seeds = [b"ticket-mint", lottery.key().as_ref(), ticket_number.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub ticket_mint: Account<'info, Mint>,
+
+    #[account(
+        mut,
+        constraint = ticket_token.mint == ticket_mint.key(),
+        constraint = ticket_token.owner == user.key()
+    )]
+    pub ticket_token: Account<'info, TokenAccount>,
+
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    ///CHECK: don't read write this contract
     #[account(mut)]
+    pub user_token: AccountInfo<'info>,
+
+    #[account(
+        mut,
+        seeds = [b"prize", lottery.key().as_ref()],
+        bump = lottery.prize_bump
+    )]
     pub prize: Account<'info, TokenAccount>,
     pub mint: Account<'info, Mint>,
 
     pub token_program: Program<'info, Token>,
     pub associated_token_program: Program<'info, AssociatedToken>,
     pub system_program: Program<'info, System>,
-    pub app_stats: Account<'info, AppStats>,
 }
 
-#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize,PartialEq)]
+#[derive(Accounts)]
+pub struct SweepRollover<'info> {
+    #[account(mut)]
+    pub lottery: Account<'info, Lottery>,
+
+    #[account(
+        mut,
+        seeds = [b"prize", lottery.key().as_ref()],
+        bump = lottery.prize_bump
+    )]
+    pub prize: Account<'info, TokenAccount>,
+
+    #[account(mut, address = lottery.rollover_account)]
+    pub rollover_token: Account<'info, TokenAccount>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct CreateReferrerStats<'info> {
+    #[account(mut)]
+    pub signer: Signer<'info>,
+
+    #[account(
+        init,
+        payer = signer,
+        space = 8 + 32 * 3 + 8 * 4 + 1 + 1,
+        seeds = [b"referrer", signer.key().as_ref(), mint.key().as_ref()],
+        bump
+    )]
+    pub referrer_stats: Account<'info, ReferrerStats>,
+
+    #[account(
+        init,
+        seeds = [b"referral-vault", referrer_stats.key().as_ref()],
+        bump,
+        payer = signer,
+        token::mint = mint,
+        token::authority = referral_vault
+    )]
+    pub referral_vault: Account<'info, TokenAccount>,
+
+    pub mint: Account<'info, Mint>,
+    pub token_program: Program<'info, Token>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ClaimReferralRewards<'info> {
+    #[account(
+        mut,
+        has_one = referrer,
+        seeds = [b"referrer", referrer.key().as_ref(), referrer_stats.mint.as_ref()],
+        bump = referrer_stats.bump
+    )]
+    pub referrer_stats: Account<'info, ReferrerStats>,
+
+    #[account(mut, address = referrer_stats.vault)]
+    pub referral_vault: Account<'info, TokenAccount>,
+
+    #[account(mut, constraint = referrer_token.mint == referrer_stats.mint)]
+    pub referrer_token: Account<'info, TokenAccount>,
+
+    pub referrer: Signer<'info>,
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Debug, Clone, Default, AnchorSerialize, AnchorDeserialize,PartialEq)]
 pub enum LotteryStatus {
+    #[default]
     Unresolved,
     Running,
     Ended,
+    // the randomness is known and the draw runs in batches, see continue_draw
+    Drawing,
+}
+
+#[derive(Debug, Clone, Default, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub enum LotteryMode {
+    #[default]
+    Raffle,
+    PickNumbers,
+}
+
+#[derive(Debug, Clone, Default, AnchorSerialize, AnchorDeserialize, PartialEq)]
+pub enum RandomnessSource {
+    #[default]
+    CommitReveal,
+    Oracle,
+    SlotHash,
 }
 
 #[error_code]
@@ -601,4 +2929,70 @@ pub enum ErrCode {
     BuyerListEmpty,
     #[msg("Maximum tickets per buyer reached")]
     MaxTicketsPerBuyer,
+    #[msg("Secret does not match the commitment")]
+    InvalidSecret,
+    #[msg("Lottery round not ended")]
+    RoundNotEnded,
+    #[msg("Lottery is not running")]
+    LotteryNotRunning,
+    #[msg("Reveal window is still open")]
+    RevealWindowOpen,
+    #[msg("Lottery uses a different randomness source")]
+    WrongRandomnessSource,
+    #[msg("Invalid randomness account")]
+    InvalidRandomnessAccount,
+    #[msg("Randomness already requested")]
+    RandomnessAlreadyRequested,
+    #[msg("Randomness not requested")]
+    RandomnessNotRequested,
+    #[msg("Randomness already fulfilled")]
+    RandomnessAlreadyFulfilled,
+    #[msg("Randomness not fulfilled yet")]
+    RandomnessNotFulfilled,
+    #[msg("Target slot not reached")]
+    TargetSlotNotReached,
+    #[msg("Target slot hash is no longer available, recommit the target slot")]
+    SlotHashExpired,
+    #[msg("Target slot hash is still available")]
+    SlotHashAvailable,
+    #[msg("Ticket pool account is too small for the ticket amount")]
+    InvalidTicketPool,
+    #[msg("Lottery does not mint ticket tokens")]
+    NftTicketsDisabled,
+    #[msg("You are not the seller")]
+    InvalidSeller,
+    #[msg("Listing is void, the winners were drawn")]
+    ListingVoided,
+    #[msg("Ticket listings must be cancelled first")]
+    TicketListed,
+    #[msg("Invalid picked numbers")]
+    InvalidPicks,
+    #[msg("Invalid prize tiers")]
+    InvalidPrizeTiers,
+    #[msg("Invalid price tiers")]
+    InvalidPriceTiers,
+    #[msg("Gift memo is too long")]
+    MemoTooLong,
+    #[msg("Invalid referrer")]
+    InvalidReferrer,
+    #[msg("Wallet is not on the lottery allowlist")]
+    NotAllowlisted,
+    #[msg("Gate token balance is too low")]
+    GateBalanceTooLow,
+    #[msg("Ticket tokens are not allowed in capped, allowlisted or gated rounds")]
+    RestrictedNftTickets,
+    #[msg("Only drawn pick-numbers rounds have a rollover")]
+    NoRollover,
+    #[msg("No draw is running")]
+    NotDrawing,
+    #[msg("Target slot hash is already saved")]
+    SlotHashSaved,
+    #[msg("Invalid round duration")]
+    InvalidRoundDuration,
+    #[msg("Reveal window is closed")]
+    RevealWindowClosed,
+    #[msg("Too many tickets in one purchase")]
+    PurchaseTooLarge,
+    #[msg("Rollover account must hold the prize token for the creator")]
+    InvalidRolloverAccount,
 }
\ No newline at end of file
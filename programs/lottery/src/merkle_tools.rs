@@ -0,0 +1,30 @@
+use sha2::{Sha256, Digest};
+
+/// Leaf of a lottery allowlist: `sha256(address || allowance.to_le_bytes())`, 36 byte preimage,
+/// where `allowance: u32` is the most tickets the address may hold, 0 for no limit beyond the
+/// lottery's own cap.
+pub fn allowlist_leaf(address: &[u8; 32], allowance: u32) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(address)
+        .chain_update(allowance.to_le_bytes())
+        .finalize()
+        .into()
+}
+
+/// Parent of two nodes: `sha256(min(a, b) || max(a, b))`, 64 byte preimage.
+/// Children are ordered by byte value, so proofs carry no left/right flags.
+pub fn hash_pair(a: &[u8; 32], b: &[u8; 32]) -> [u8; 32] {
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    Sha256::new()
+        .chain_update(low)
+        .chain_update(high)
+        .finalize()
+        .into()
+}
+
+/// Folds the sibling hashes of `proof`, leaf level first, into `leaf` with `hash_pair`
+/// and checks the result against `root`.
+pub fn verify_proof(root: &[u8; 32], leaf: &[u8; 32], proof: &[[u8; 32]]) -> bool {
+    let node = proof.iter().fold(*leaf, |node, sibling| hash_pair(&node, sibling));
+    &node == root
+}
@@ -1,48 +1,135 @@
-// use anchor_lang::solana_program::keccak;
-// use std::convert::TryInto;
-
-// //https://docs.chain.link/docs/chainlink-vrf-best-practices/#getting-multiple-random-number
-// pub fn expand(randomness: Vec<u8>, n: u32) -> u32 {
-//     let mut hasher = keccak::Hasher::default();
-//     hasher.hash(&randomness);
-//     hasher.hash(&n.to_le_bytes());
-
-//     u32::from_le_bytes(
-//         hasher.result().to_bytes()[0..4]
-//             .try_into()
-//             .expect("slice with incorrect length"),
-//     )
-// }
-
 use sha2::{Sha256, Digest};
 
-pub struct HashStruct {
-    pub nonce : u64,
-    pub initial_seed : u64
+/// Counter-mode sha256 stream of u64s, the same idea as Chainlink's `expand`
+/// (https://docs.chain.link/docs/chainlink-vrf-best-practices/#getting-multiple-random-number).
+///
+/// Byte layout, kept stable so off-chain tools can reproduce every draw:
+/// - block `i` is `sha256(seed[0..32] || i.to_le_bytes())`, a 40 byte preimage with `i: u64`
+///   starting at 0
+/// - each block yields 4 values, `u64::from_le_bytes(block[0..8])`, then `[8..16]`, `[16..24]`
+///   and `[24..32]`, before moving to block `i + 1`
+///
+/// `uniform_below` and `partial_shuffle` only consume values from this sequence, so their
+/// results are reproducible from the seed as well.
+pub struct RandomnessStream {
+    seed: [u8; 32],
+    counter: u64,
+    block: [u8; 32],
+    offset: usize,
 }
 
-unsafe fn any_as_u8_slice<T: Sized>(p: &T) -> &[u8] {
-    ::std::slice::from_raw_parts(
-        (p as *const T) as *const u8,
-        ::std::mem::size_of::<T>(),
-    )
-}
+impl RandomnessStream {
+    pub const VALUES_PER_BLOCK: usize = 4;
 
-pub fn get_sha256_hashed_random(seed: u64, nonce: u64) -> u64 {
+    pub fn new(seed: [u8; 32]) -> Self {
+        RandomnessStream {
+            seed,
+            counter: 0,
+            block: [0u8; 32],
+            // forces the first call to next_u64 to hash block 0
+            offset: Self::VALUES_PER_BLOCK,
+        }
+    }
+
+    /// Stream seeded with `seed.to_le_bytes() || nonce.to_le_bytes() || [0; 16]`.
+    pub fn from_seed_and_nonce(seed: u64, nonce: u64) -> Self {
+        let mut stream_seed = [0u8; 32];
+        stream_seed[..8].copy_from_slice(&seed.to_le_bytes());
+        stream_seed[8..16].copy_from_slice(&nonce.to_le_bytes());
+        RandomnessStream::new(stream_seed)
+    }
+
+    /// Stream seeded with `seed` after `position` values were taken from it, see `position()`,
+    /// so a draw spread over several transactions consumes the same values as one running in a single transaction.
+    pub fn resume(seed: [u8; 32], position: u64) -> Self {
+        let mut stream = RandomnessStream::new(seed);
+        if position > 0 {
+            // the block holding the last value taken is hashed again
+            stream.counter = position.div_ceil(Self::VALUES_PER_BLOCK as u64);
+            stream.offset = (position - (stream.counter - 1) * Self::VALUES_PER_BLOCK as u64) as usize;
+            stream.block = hash_block(&seed, stream.counter - 1);
+        }
+        stream
+    }
+
+    /// Values taken from the stream so far.
+    pub fn position(&self) -> u64 {
+        match self.counter {
+            0 => 0,
+            counter => (counter - 1) * Self::VALUES_PER_BLOCK as u64 + self.offset as u64,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        if self.offset == Self::VALUES_PER_BLOCK {
+            self.block = hash_block(&self.seed, self.counter);
+            self.counter += 1;
+            self.offset = 0;
+        }
+
+        let start = self.offset * 8;
+        self.offset += 1;
+        u64::from_le_bytes(self.block[start..start + 8].try_into().expect("slice with incorrect length"))
+    }
+
+    /// Unbiased value in `0..n` by rejection sampling.
+    /// Values below `2^64 % n` are skipped so every residue has the same number of preimages,
+    /// the first accepted value `v` gives `v % n`. Panics if `n` is zero.
+    pub fn uniform_below(&mut self, n: u64) -> u64 {
+        assert!(n > 0, "uniform_below called with n = 0");
+        let threshold = n.wrapping_neg() % n;
+        loop {
+            let v = self.next_u64();
+            if v >= threshold {
+                return v % n;
+            }
+        }
+    }
 
-    let hashstruct = HashStruct {nonce : nonce, initial_seed : seed};
-    let vec_to_hash = unsafe{any_as_u8_slice(&hashstruct)};
-    let hash= &(Sha256::new()
-    .chain_update(vec_to_hash)
-    .finalize()[..32]);
-
-    // hash is a vector of 32 8bit numbers.  We can take slices of this to generate our 4 random u64s
-    let mut hashed_randoms : [u64; 4] = [0; 4];
-    for i in 0..1 {
-        let hash_slice = &hash[i*8..(i+1)*8];
-        hashed_randoms[i] = u64::from_le_bytes(hash_slice.try_into().expect("slice with incorrect length"));
+    /// Partial Fisher-Yates shuffle: for `i` in `0..k`, swaps `items[i]` with
+    /// `items[i + uniform_below(len - i)]`. The first `k` items are then a uniformly chosen
+    /// ordered sample without replacement and are returned. `k` is capped at `items.len()`.
+    pub fn partial_shuffle<'a, T>(&mut self, items: &'a mut [T], k: usize) -> &'a [T] {
+        let k = k.min(items.len());
+        for i in 0..k {
+            let j = i + self.uniform_below((items.len() - i) as u64) as usize;
+            items.swap(i, j);
+        }
+        &items[..k]
     }
+}
+
+fn hash_block(seed: &[u8; 32], counter: u64) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(seed)
+        .chain_update(counter.to_le_bytes())
+        .finalize()
+        .into()
+}
 
-    return hashed_randoms[0];
-    
-}
\ No newline at end of file
+/// Folds a buyer's client seed into the round accumulator:
+/// `sha256(accumulator || participant || client_seed)`, 96 byte preimage.
+pub fn fold_entropy(accumulator: &[u8; 32], participant: &[u8; 32], client_seed: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(accumulator)
+        .chain_update(participant)
+        .chain_update(client_seed)
+        .finalize()
+        .into()
+}
+
+/// Seed of the draw stream: `sha256(randomness || accumulator)`, 64 byte preimage, where
+/// `randomness` is the oracle output or the slot hash. Commit-reveal rounds pass
+/// `mix_entropy(secret, slot_hash)` as `randomness`.
+pub fn mix_entropy(randomness: &[u8; 32], accumulator: &[u8; 32]) -> [u8; 32] {
+    Sha256::new()
+        .chain_update(randomness)
+        .chain_update(accumulator)
+        .finalize()
+        .into()
+}
+
+/// First value of the stream seeded with `seed.to_le_bytes() || nonce.to_le_bytes() || [0; 16]`.
+pub fn get_sha256_hashed_random(seed: u64, nonce: u64) -> u64 {
+    RandomnessStream::from_seed_and_nonce(seed, nonce).next_u64()
+}
@@ -0,0 +1,22 @@
+// Fixtures shared by the integration tests, each test crate uses the ones it needs.
+
+#![allow(dead_code)]
+
+use anchor_lang::prelude::Pubkey;
+use lottery::TicketPool;
+
+// 32 bytes from 64 hex digits, for hashes pinned by the known-answer tests
+pub fn hex(s: &str) -> [u8; 32] {
+    let mut bytes = [0u8; 32];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[2 * i..2 * i + 2], 16).unwrap();
+    }
+    bytes
+}
+
+// an empty pool header and its ticket bytes, sized the way create_lottery sizes them
+pub fn pool(capacity: u32, pick_count: u8) -> (TicketPool, Vec<u8>) {
+    let ticket_pool = TicketPool { lottery: Pubkey::default(), capacity, remaining: capacity, pick_count: pick_count as u32 };
+    let pool_tickets = vec![0u8; TicketPool::space(capacity, pick_count) - TicketPool::HEADER_SIZE];
+    (ticket_pool, pool_tickets)
+}
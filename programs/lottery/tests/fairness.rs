@@ -0,0 +1,244 @@
+// Statistical fairness simulator for randomness_tools and the lottery draw.
+//
+// Runs the same code paths as buy_tickets and reveal_winners over many simulated rounds and
+// checks the results with chi-square and Kolmogorov-Smirnov tests at p = 0.001.
+// Every round is seeded from a fixed master seed, so results are reproducible.
+//
+//     cargo test --release -p lottery --test fairness -- --nocapture
+//
+// Debug builds run 100x fewer rounds so `cargo test --workspace` stays fast.
+
+use anchor_lang::prelude::Pubkey;
+use lottery::randomness_tools::RandomnessStream;
+use lottery::{ drawn_numbers, TicketPool };
+
+const ROUNDS: usize = if cfg!(debug_assertions) { 20_000 } else { 2_000_000 };
+
+// z-score of the upper 0.1% tail of the normal distribution
+const Z_999: f64 = 3.090;
+// Kolmogorov distribution quantile for p = 0.001
+const KS_999: f64 = 1.949;
+
+fn chi_square(observed: &[u64], expected: &[f64]) -> f64 {
+    observed.iter().zip(expected.iter())
+        .map(|(&o, &e)| (o as f64 - e).powi(2) / e)
+        .sum()
+}
+
+// Wilson-Hilferty approximation of the chi-square quantile for p = 0.001
+fn chi_square_critical(df: usize) -> f64 {
+    let df = df as f64;
+    let h = 2.0 / (9.0 * df);
+    df * (1.0 - h + Z_999 * h.sqrt()).powi(3)
+}
+
+fn assert_chi_square(name: &str, observed: &[u64], expected: &[f64]) {
+    let stat = chi_square(observed, expected);
+    let critical = chi_square_critical(observed.len() - 1);
+    println!("{}: chi-square {:.2}, critical {:.2}, df {}", name, stat, critical, observed.len() - 1);
+    assert!(stat < critical, "{} failed the chi-square test: {:.2} >= {:.2}", name, stat, critical);
+}
+
+// samples must be in [0, 1), compared against the uniform CDF
+fn assert_ks_uniform(name: &str, samples: &mut [f64]) {
+    samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = samples.len() as f64;
+    let d = samples.iter().enumerate()
+        .map(|(i, &x)| (x - i as f64 / n).max((i + 1) as f64 / n - x))
+        .fold(0.0, f64::max);
+    let critical = KS_999 / n.sqrt();
+    println!("{}: KS D {:.6}, critical {:.6}, n {}", name, d, critical, samples.len());
+    assert!(d < critical, "{} failed the KS test: {:.6} >= {:.6}", name, d, critical);
+}
+
+// per-round seeds derived from a fixed master stream
+fn round_seeds(label: u64) -> impl FnMut() -> [u8; 32] {
+    let mut master = RandomnessStream::from_seed_and_nonce(0x6c6f_7474_6572_7921, label);
+    move || {
+        let mut seed = [0u8; 32];
+        for chunk in seed.chunks_mut(8) {
+            chunk.copy_from_slice(&master.next_u64().to_le_bytes());
+        }
+        seed
+    }
+}
+
+// randomized probability integral transform, spreads a discrete `index` in `0..n` uniformly
+// over its bucket of [0, 1) so the continuous KS test applies
+fn spread(index: usize, n: usize, jitter: &mut RandomnessStream) -> f64 {
+    (index as f64 + jitter.next_u64() as f64 / 2f64.powi(64)) / n as f64
+}
+
+// owner index of every ticket number, one buyer per entry of `tickets_per_buyer` holding
+// consecutive ticket numbers starting at 1, index 0 is unused
+fn owners_with(tickets_per_buyer: &[u32]) -> Vec<usize> {
+    let mut owners = vec![usize::MAX];
+    for (buyer, &count) in tickets_per_buyer.iter().enumerate() {
+        owners.extend(std::iter::repeat(buyer).take(count as usize));
+    }
+    owners
+}
+
+#[test]
+fn stream_outputs_are_uniform() {
+    let mut next_seed = round_seeds(1);
+    let mut stream = RandomnessStream::new(next_seed());
+    let mut top_bytes = vec![0u64; 256];
+    let mut samples: Vec<f64> = Vec::with_capacity(ROUNDS);
+    for _ in 0..ROUNDS {
+        let v = stream.next_u64();
+        top_bytes[(v >> 56) as usize] += 1;
+        samples.push(v as f64 / 2f64.powi(64));
+    }
+    assert_chi_square("next_u64 top byte", &top_bytes, &vec![ROUNDS as f64 / 256.0; 256]);
+    assert_ks_uniform("next_u64", &mut samples);
+}
+
+#[test]
+fn uniform_below_has_no_modulo_bias() {
+    let mut next_seed = round_seeds(2);
+    for n in [2u64, 3, 7, 10, 100, 255] {
+        let mut stream = RandomnessStream::new(next_seed());
+        let mut counts = vec![0u64; n as usize];
+        for _ in 0..ROUNDS {
+            counts[stream.uniform_below(n) as usize] += 1;
+        }
+        assert_chi_square(&format!("uniform_below({})", n), &counts, &vec![ROUNDS as f64 / n as f64; n as usize]);
+    }
+
+    // with a plain `%` the lowest third of this range would be hit twice as often
+    let n = (1u64 << 63) + (1u64 << 62);
+    let mut stream = RandomnessStream::new(next_seed());
+    let mut thirds = vec![0u64; 3];
+    for _ in 0..ROUNDS {
+        thirds[(stream.uniform_below(n) >> 62) as usize] += 1;
+    }
+    assert_chi_square("uniform_below(3 * 2^62) thirds", &thirds, &[ROUNDS as f64 / 3.0; 3]);
+
+    let n = 1_000_003u64;
+    let mut stream = RandomnessStream::new(next_seed());
+    let mut jitter = RandomnessStream::new(next_seed());
+    let mut samples: Vec<f64> = (0..ROUNDS).map(|_| spread(stream.uniform_below(n) as usize, n as usize, &mut jitter)).collect();
+    assert_ks_uniform("uniform_below(1000003)", &mut samples);
+}
+
+#[test]
+fn ticket_assignment_is_uniform() {
+    let mut next_seed = round_seeds(3);
+    let mut jitter = RandomnessStream::new(next_seed());
+    for left in [10u32, 37, 100, 255, 10_000] {
+        let rounds = ROUNDS / 10;
+        // large pools are folded into at most 100 buckets so every bin has enough samples
+        let buckets = left.min(100) as usize;
+        let bucket_of = |ticket: u32| (ticket as usize - 1) * buckets / left as usize;
+        let mut bucket_sizes = vec![0u64; buckets];
+        for ticket in 1..=left {
+            bucket_sizes[bucket_of(ticket)] += 1;
+        }
+        let mut first_pick = vec![0u64; buckets];
+        let mut positions: Vec<f64> = Vec::with_capacity(rounds);
+        for _ in 0..rounds {
+            let mut pool = TicketPool { lottery: Pubkey::default(), capacity: left, remaining: left, pick_count: 0 };
+            let mut pool_tickets = vec![0u8; TicketPool::space(left, 0) - TicketPool::HEADER_SIZE];
+            let mut stream = RandomnessStream::new(next_seed());
+            // a purchase of 5 tickets, as buy_tickets would assign them
+            let tickets: Vec<u32> = (0..5).map(|_| pool.take_random_ticket(&mut pool_tickets, &mut stream)).collect();
+            let mut sorted = tickets.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), tickets.len(), "a ticket was assigned twice");
+            first_pick[bucket_of(tickets[0])] += 1;
+            positions.push(spread(tickets[0] as usize - 1, left as usize, &mut jitter));
+        }
+        let expected: Vec<f64> = bucket_sizes.iter().map(|&size| rounds as f64 * size as f64 / left as f64).collect();
+        assert_chi_square(&format!("first ticket of {}", left), &first_pick, &expected);
+        assert_ks_uniform(&format!("first ticket of {}", left), &mut positions);
+    }
+}
+
+#[test]
+fn winner_selection_is_ticket_weighted() {
+    let mut next_seed = round_seeds(4);
+    let mut jitter = RandomnessStream::new(next_seed());
+    let distributions: [&[u32]; 5] = [
+        &[5, 5, 5, 5],
+        &[1, 1, 1, 1, 1, 5, 10],
+        &[1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20],
+        &[1, 50, 3, 25, 2, 100],
+        &[1, 300, 7, 1200],
+    ];
+    for tickets_per_buyer in distributions.iter() {
+        let rounds = ROUNDS / 10;
+        let sold: usize = tickets_per_buyer.iter().map(|&count| count as usize).sum();
+        let owners = owners_with(tickets_per_buyer);
+        let sold_tickets: Vec<u32> = (1..=sold as u32).collect();
+        let winning = sold / 2;
+
+        let mut ticket_wins = vec![0u64; sold + 1];
+        let mut buyer_wins = vec![0u64; tickets_per_buyer.len()];
+        let mut first_drawn: Vec<f64> = Vec::with_capacity(rounds);
+        for _ in 0..rounds {
+            // a sold-out pool, slot i holds ticket i + 1, drawn in one batch like the raffle draw
+            let pool = TicketPool { lottery: Pubkey::default(), capacity: sold as u32, remaining: 0, pick_count: 0 };
+            let mut pool_tickets = vec![0u8; TicketPool::space(sold as u32, 0) - TicketPool::HEADER_SIZE];
+            let mut stream = RandomnessStream::new(next_seed());
+            let drawn = pool.draw_tickets(&mut pool_tickets, &mut stream, 0, winning as u32);
+            assert_eq!(drawn.len(), winning);
+
+            let mut tickets = drawn.to_vec();
+            tickets.sort();
+            tickets.dedup();
+            assert_eq!(tickets.len(), winning, "a ticket won twice in one draw");
+
+            for &ticket in drawn.iter() {
+                ticket_wins[ticket as usize] += 1;
+                buyer_wins[owners[ticket as usize]] += 1;
+            }
+            let position = sold_tickets.iter().position(|&ticket| ticket == drawn[0]).unwrap();
+            first_drawn.push(spread(position, sold, &mut jitter));
+        }
+
+        // every sold ticket wins with probability winning / sold
+        let per_ticket = rounds as f64 * winning as f64 / sold as f64;
+        let observed: Vec<u64> = sold_tickets.iter().map(|&ticket| ticket_wins[ticket as usize]).collect();
+        let name = format!("{:?}", tickets_per_buyer);
+        assert_chi_square(&format!("ticket wins {}", name), &observed, &vec![per_ticket; sold]);
+
+        // a wallet's wins follow its share of tickets
+        let expected: Vec<f64> = tickets_per_buyer.iter().map(|&count| per_ticket * count as f64).collect();
+        assert_chi_square(&format!("buyer wins {}", name), &buyer_wins, &expected);
+
+        assert_ks_uniform(&format!("first drawn {}", name), &mut first_drawn);
+    }
+}
+
+#[test]
+fn drawn_numbers_are_uniform() {
+    let mut next_seed = round_seeds(5);
+    for (pick_count, pick_range) in [(1u8, 7u8), (5, 10), (6, 49), (10, 255)] {
+        let rounds = ROUNDS / 10;
+        let mut counts = vec![0u64; pick_range as usize];
+        let mut first_number = vec![0u64; pick_range as usize];
+        for _ in 0..rounds {
+            let numbers = drawn_numbers(pick_count, pick_range, &next_seed());
+            assert_eq!(numbers.len(), pick_count as usize);
+
+            let mut sorted = numbers.clone();
+            sorted.sort();
+            sorted.dedup();
+            assert_eq!(sorted.len(), numbers.len(), "a number was drawn twice");
+            assert!(sorted[0] >= 1 && sorted[sorted.len() - 1] <= pick_range, "number out of range");
+
+            for &number in numbers.iter() {
+                counts[number as usize - 1] += 1;
+            }
+            first_number[numbers[0] as usize - 1] += 1;
+        }
+
+        // every number is drawn with probability pick_count / pick_range
+        let name = format!("{} of {}", pick_count, pick_range);
+        let per_number = rounds as f64 * pick_count as f64 / pick_range as f64;
+        assert_chi_square(&format!("drawn numbers {}", name), &counts, &vec![per_number; pick_range as usize]);
+        assert_chi_square(&format!("first number {}", name), &first_number, &vec![rounds as f64 / pick_range as f64; pick_range as usize]);
+    }
+}
@@ -0,0 +1,107 @@
+// Allowlist proofs: leaves are allowlist_leaf(wallet, allowance), parents hash_pair of their
+// children, and buy_tickets accepts a wallet whose proof folds up to the lottery's allowlist_root.
+// The leaf and pair hashes are pinned, an allowlist built off chain must produce the same root.
+//
+//     cargo test -p lottery --test merkle
+
+mod common;
+
+use common::hex;
+use lottery::merkle_tools::{ allowlist_leaf, hash_pair, verify_proof };
+
+// tree levels from the leaves up, an odd node out is carried to the next level unchanged
+fn tree(leaves: &[[u8; 32]]) -> Vec<Vec<[u8; 32]>> {
+    let mut levels = vec![leaves.to_vec()];
+    while levels[levels.len() - 1].len() > 1 {
+        let next = levels[levels.len() - 1].chunks(2)
+            .map(|pair| match pair {
+                [a, b] => hash_pair(a, b),
+                [a] => *a,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    levels
+}
+
+fn proof(levels: &[Vec<[u8; 32]>], mut index: usize) -> Vec<[u8; 32]> {
+    let mut proof = Vec::new();
+    for level in levels[..levels.len() - 1].iter() {
+        if let Some(sibling) = level.get(index ^ 1) {
+            proof.push(*sibling);
+        }
+        index /= 2;
+    }
+    proof
+}
+
+fn wallet(i: u8) -> [u8; 32] {
+    [i; 32]
+}
+
+#[test]
+fn leaf_layout() {
+    assert_eq!(allowlist_leaf(&wallet(1), 5), hex("05d7a0f0e24948b3497153cebd1cf554cc16aaa4ec551dbb835f747d7da0b85b"));
+    assert_eq!(allowlist_leaf(&wallet(2), 0), hex("2703b5a5a85a25acb3a2fc14620d517f3a7df15f9975ddef7556c209106dced9"));
+}
+
+#[test]
+fn pair_order_does_not_matter() {
+    let a = allowlist_leaf(&wallet(1), 5);
+    let b = allowlist_leaf(&wallet(2), 0);
+    let parent = hex("a5f243ffb927487f8c6920a151265db1ca37efa72daaa451f7d1dbcc4e374e12");
+    assert_eq!(hash_pair(&a, &b), parent);
+    assert_eq!(hash_pair(&b, &a), parent);
+}
+
+#[test]
+fn single_leaf_tree() {
+    let leaf = allowlist_leaf(&wallet(1), 3);
+    assert!(verify_proof(&leaf, &leaf, &[]));
+    assert!(!verify_proof(&leaf, &allowlist_leaf(&wallet(1), 4), &[]));
+}
+
+#[test]
+fn every_leaf_proves() {
+    for size in [2usize, 3, 4, 5, 8, 13] {
+        let leaves: Vec<[u8; 32]> = (0..size).map(|i| allowlist_leaf(&wallet(i as u8), i as u32)).collect();
+        let levels = tree(&leaves);
+        let root = levels[levels.len() - 1][0];
+        for (i, leaf) in leaves.iter().enumerate() {
+            assert!(verify_proof(&root, leaf, &proof(&levels, i)), "leaf {} of {}", i, size);
+        }
+    }
+}
+
+#[test]
+fn rejects_other_allowance() {
+    let leaves: Vec<[u8; 32]> = (0..5).map(|i| allowlist_leaf(&wallet(i), 2)).collect();
+    let levels = tree(&leaves);
+    let root = levels[levels.len() - 1][0];
+    let proof = proof(&levels, 3);
+    assert!(verify_proof(&root, &allowlist_leaf(&wallet(3), 2), &proof));
+    assert!(!verify_proof(&root, &allowlist_leaf(&wallet(3), 0), &proof));
+    assert!(!verify_proof(&root, &allowlist_leaf(&wallet(3), 10), &proof));
+}
+
+#[test]
+fn rejects_other_wallet_and_bad_proofs() {
+    let leaves: Vec<[u8; 32]> = (0..5).map(|i| allowlist_leaf(&wallet(i), 2)).collect();
+    let levels = tree(&leaves);
+    let root = levels[levels.len() - 1][0];
+    let good = proof(&levels, 1);
+
+    // someone else's proof, a wallet that is not listed
+    assert!(!verify_proof(&root, &allowlist_leaf(&wallet(1), 2), &proof(&levels, 2)));
+    assert!(!verify_proof(&root, &allowlist_leaf(&wallet(9), 2), &good));
+
+    // truncated, extended and tampered proofs
+    assert!(!verify_proof(&root, &leaves[1], &good[..good.len() - 1]));
+    let mut extended = good.clone();
+    extended.push(leaves[0]);
+    assert!(!verify_proof(&root, &leaves[1], &extended));
+    let mut tampered = good.clone();
+    tampered[0][0] ^= 1;
+    assert!(!verify_proof(&root, &leaves[1], &tampered));
+}
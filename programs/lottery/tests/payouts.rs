@@ -0,0 +1,114 @@
+// What claims pay: raffle winners share the collected amount, pick-numbers tickets split the pot
+// of their best prize tier and what no ticket is paid rolls over, refunds give back what each ticket paid.
+//
+//     cargo test -p lottery --test payouts
+
+mod common;
+
+use common::pool;
+use lottery::{ Lottery, LotteryMode, LotteryStatus, PrizeTier, MAX_TICKET_PRICE };
+
+const CAPACITY: u32 = 8;
+
+// three numbers out of 1..=8 drawn as 1, 2, 3, tiers from three and two matches
+fn pick_numbers(collected: u64, tier_winners: &[u32]) -> Lottery {
+    Lottery {
+        mode: LotteryMode::PickNumbers,
+        status: LotteryStatus::Ended,
+        pick_count: 3,
+        pick_range: 8,
+        winning_numbers: vec![1, 2, 3],
+        prize_tiers: vec![PrizeTier { matches: 3, percent: 60 }, PrizeTier { matches: 2, percent: 40 }],
+        tier_winners: tier_winners.to_vec(),
+        collected,
+        ..Lottery::default()
+    }
+}
+
+#[test]
+fn tickets_reach_their_best_tier() {
+    let lottery = pick_numbers(1_000, &[0, 0]);
+    assert_eq!(lottery.best_tier(3), Some(0));
+    assert_eq!(lottery.best_tier(2), Some(1));
+    assert_eq!(lottery.best_tier(1), None);
+    assert_eq!(lottery.best_tier(0), None);
+}
+
+#[test]
+fn tier_winners_split_their_pot() {
+    let (ticket_pool, mut pool_tickets) = pool(CAPACITY, 3);
+    ticket_pool.set_picks(&mut pool_tickets, 1, &[1, 2, 3]);
+    ticket_pool.set_picks(&mut pool_tickets, 2, &[1, 2, 4]);
+    ticket_pool.set_picks(&mut pool_tickets, 3, &[2, 3, 8]);
+    ticket_pool.set_picks(&mut pool_tickets, 4, &[1, 3, 5]);
+    ticket_pool.set_picks(&mut pool_tickets, 5, &[3, 6, 7]);
+    let lottery = pick_numbers(1_000, &[1, 3]);
+
+    assert_eq!(lottery.ticket_prize(&ticket_pool, &pool_tickets, 1), 600);
+    for ticket in [2, 3, 4] {
+        assert_eq!(lottery.ticket_prize(&ticket_pool, &pool_tickets, ticket), 133);
+    }
+    assert_eq!(lottery.ticket_prize(&ticket_pool, &pool_tickets, 5), 0);
+    // every tier was won, only the remainder of the three-way split rolls over
+    assert_eq!(lottery.rollover_amount(), 1);
+}
+
+#[test]
+fn unwon_tiers_roll_over() {
+    let (ticket_pool, mut pool_tickets) = pool(CAPACITY, 3);
+    ticket_pool.set_picks(&mut pool_tickets, 1, &[1, 2, 4]);
+    ticket_pool.set_picks(&mut pool_tickets, 2, &[1, 3, 5]);
+    ticket_pool.set_picks(&mut pool_tickets, 3, &[4, 5, 6]);
+    let lottery = pick_numbers(999, &[0, 2]);
+
+    assert_eq!(lottery.tier_pot(0), 599);
+    assert_eq!(lottery.tier_pot(1), 399);
+    assert_eq!(lottery.ticket_prize(&ticket_pool, &pool_tickets, 1), 199);
+    assert_eq!(lottery.ticket_prize(&ticket_pool, &pool_tickets, 2), 199);
+    assert_eq!(lottery.ticket_prize(&ticket_pool, &pool_tickets, 3), 0);
+    // the unwon pot, the remainder of the second tier's split and the token the percents left out
+    assert_eq!(lottery.rollover_amount(), 599 + 1 + 1);
+
+    let nobody_won = pick_numbers(999, &[0, 0]);
+    assert_eq!(nobody_won.rollover_amount(), 999);
+}
+
+#[test]
+fn raffle_winners_share_the_collected_amount() {
+    let (ticket_pool, mut pool_tickets) = pool(CAPACITY, 0);
+    for ticket in [1, 4, 6] {
+        ticket_pool.set_winner(&mut pool_tickets, ticket, true);
+    }
+    let lottery = Lottery {
+        status: LotteryStatus::Ended,
+        winning_ticket_count: 3,
+        collected: 1_000,
+        ..Lottery::default()
+    };
+
+    for ticket in [1, 4, 6] {
+        assert_eq!(lottery.ticket_prize(&ticket_pool, &pool_tickets, ticket), 333);
+    }
+    for ticket in [2, 3, 5, 7, 8] {
+        assert_eq!(lottery.ticket_prize(&ticket_pool, &pool_tickets, ticket), 0);
+    }
+}
+
+#[test]
+fn refunds_give_back_what_each_ticket_paid() {
+    let (ticket_pool, mut pool_tickets) = pool(CAPACITY, 0);
+    // a bundle of three tickets that paid 1,000 after fees, then a single ticket
+    ticket_pool.set_paid_shares(&mut pool_tickets, &[5, 2, 7], 1_000);
+    ticket_pool.set_paid_shares(&mut pool_tickets, &[3], 400);
+
+    assert_eq!(ticket_pool.paid_for(&pool_tickets, 5), 334);
+    assert_eq!(ticket_pool.paid_for(&pool_tickets, 2), 333);
+    assert_eq!(ticket_pool.paid_for(&pool_tickets, 7), 333);
+    assert_eq!(ticket_pool.paid_for(&pool_tickets, 3), 400);
+    // claim_prize refunds the sum over a receipt's tickets, the bundle gets back what it paid
+    let refund: u64 = [5, 2, 7].iter().map(|&ticket| ticket_pool.paid_for(&pool_tickets, ticket)).sum();
+    assert_eq!(refund, 1_000);
+
+    ticket_pool.set_paid_shares(&mut pool_tickets, &[8], MAX_TICKET_PRICE);
+    assert_eq!(ticket_pool.paid_for(&pool_tickets, 8), MAX_TICKET_PRICE);
+}
@@ -0,0 +1,133 @@
+// Bulk-discount pricing: buy_tickets charges Lottery::price_of, the cheapest combination of the
+// price schedule's bundles with single tickets at ticket_price. Resales pay royalty_of the listing price.
+//
+//     cargo test -p lottery --test pricing
+
+use lottery::{ royalty_of, Lottery, PriceTier, MAX_BUNDLE_TICKETS, MAX_PRICE_TIERS, MAX_PURCHASE_TICKETS, MAX_TICKET_PRICE };
+
+fn lottery(ticket_price: u64, price_tiers: &[(u32, u64)]) -> Lottery {
+    Lottery {
+        ticket_price,
+        price_tiers: price_tiers.iter()
+            .map(|&(tickets, price)| PriceTier { tickets, price })
+            .collect(),
+        ..Lottery::default()
+    }
+}
+
+#[test]
+fn no_price_tiers() {
+    let lottery = lottery(100, &[]);
+    assert_eq!(lottery.price_of(0).unwrap(), 0);
+    assert_eq!(lottery.price_of(1).unwrap(), 100);
+    assert_eq!(lottery.price_of(37).unwrap(), 3_700);
+}
+
+#[test]
+fn single_bundle() {
+    let lottery = lottery(100, &[(5, 450)]);
+    assert_eq!(lottery.price_of(0).unwrap(), 0);
+    assert_eq!(lottery.price_of(4).unwrap(), 400);
+    assert_eq!(lottery.price_of(5).unwrap(), 450);
+    assert_eq!(lottery.price_of(7).unwrap(), 650);
+    assert_eq!(lottery.price_of(10).unwrap(), 900);
+    assert_eq!(lottery.price_of(12).unwrap(), 1_100);
+}
+
+#[test]
+fn combines_bundles() {
+    let lottery = lottery(100, &[(5, 450), (10, 800)]);
+    assert_eq!(lottery.price_of(9).unwrap(), 850);
+    assert_eq!(lottery.price_of(10).unwrap(), 800);
+    assert_eq!(lottery.price_of(15).unwrap(), 1_250);
+    assert_eq!(lottery.price_of(20).unwrap(), 1_600);
+    assert_eq!(lottery.price_of(24).unwrap(), 2_000);
+}
+
+#[test]
+fn beats_largest_bundle_first() {
+    // greedy would take 4 + 1 + 1 for 520
+    let lottery = lottery(100, &[(3, 250), (4, 320)]);
+    assert_eq!(lottery.price_of(6).unwrap(), 500);
+    assert_eq!(lottery.price_of(7).unwrap(), 570);
+    assert_eq!(lottery.price_of(8).unwrap(), 640);
+}
+
+#[test]
+fn ignores_bundles_dearer_than_single_tickets() {
+    let lottery = lottery(100, &[(2, 250), (3, 290)]);
+    assert_eq!(lottery.price_of(2).unwrap(), 200);
+    assert_eq!(lottery.price_of(3).unwrap(), 290);
+    assert_eq!(lottery.price_of(5).unwrap(), 490);
+}
+
+#[test]
+fn bundle_larger_than_order() {
+    let lottery = lottery(100, &[(50, 1_000)]);
+    assert_eq!(lottery.price_of(3).unwrap(), 300);
+    assert_eq!(lottery.price_of(50).unwrap(), 1_000);
+}
+
+#[test]
+fn large_order_matches_full_table() {
+    let tiers = [(3, 250), (7, 560), (50, 3_800)];
+    let lottery = lottery(100, &tiers);
+
+    // the whole table, as the price schedule defines it
+    let mut cheapest = vec![0u64; 5_001];
+    for tickets in 1..cheapest.len() {
+        cheapest[tickets] = cheapest[tickets - 1] + 100;
+        for &(size, price) in tiers.iter() {
+            if size as usize <= tickets {
+                cheapest[tickets] = cheapest[tickets].min(cheapest[tickets - size as usize] + price);
+            }
+        }
+    }
+    for tickets in [1usize, 49, 50, 51, 99, 100, 101, 1_234, 4_999, 5_000] {
+        assert_eq!(lottery.price_of(tickets as u64).unwrap(), cheapest[tickets], "{} tickets", tickets);
+    }
+}
+
+#[test]
+fn largest_purchase_with_the_largest_schedule() {
+    // MAX_PRICE_TIERS bundles up to MAX_BUNDLE_TICKETS, priced a little under single tickets
+    let tiers: Vec<(u32, u64)> = (0..MAX_PRICE_TIERS as u32)
+        .map(|tier| MAX_BUNDLE_TICKETS - 7 * tier)
+        .map(|tickets| (tickets, 100 * tickets as u64 - tickets as u64 / 3 - 1))
+        .collect();
+    let lottery = lottery(100, &tiers);
+
+    let mut cheapest = vec![0u64; MAX_PURCHASE_TICKETS as usize + 1];
+    for tickets in 1..cheapest.len() {
+        cheapest[tickets] = cheapest[tickets - 1] + 100;
+        for &(size, price) in tiers.iter() {
+            if size as usize <= tickets {
+                cheapest[tickets] = cheapest[tickets].min(cheapest[tickets - size as usize] + price);
+            }
+        }
+    }
+    // the pricing takes one step per ticket and tier, buy_tickets refuses larger purchases
+    assert_eq!(lottery.price_of(MAX_PURCHASE_TICKETS).unwrap(), cheapest[MAX_PURCHASE_TICKETS as usize]);
+    assert!(MAX_PURCHASE_TICKETS * (MAX_PRICE_TIERS as u64 + 1) <= 1_000);
+}
+
+#[test]
+fn orders_costing_more_than_a_u64_fail() {
+    let singles = lottery(u64::MAX / 2, &[]);
+    assert_eq!(singles.price_of(2).unwrap(), u64::MAX - 1);
+    assert!(singles.price_of(3).is_err());
+
+    // the bundle is the cheaper way to two tickets, but a third still overflows
+    let bundles = lottery(u64::MAX / 2, &[(2, u64::MAX / 2 + 2)]);
+    assert_eq!(bundles.price_of(2).unwrap(), u64::MAX / 2 + 2);
+    assert!(bundles.price_of(3).is_err());
+}
+
+#[test]
+fn royalty_of_the_highest_listing_price() {
+    assert_eq!(royalty_of(MAX_TICKET_PRICE, 100).unwrap(), MAX_TICKET_PRICE);
+    assert_eq!(royalty_of(MAX_TICKET_PRICE, 10).unwrap(), MAX_TICKET_PRICE / 10);
+    assert_eq!(royalty_of(MAX_TICKET_PRICE, 0).unwrap(), 0);
+    // prices list_ticket refuses overflow instead of panicking
+    assert!(royalty_of(u64::MAX / 100 + 1, 100).is_err());
+}
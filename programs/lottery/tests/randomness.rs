@@ -0,0 +1,93 @@
+// Known-answer vectors pinning the byte layout of randomness_tools, which off-chain tools rely on
+// to reproduce every draw. The values follow from the block layout documented on RandomnessStream,
+// a change to the hashing or to the order values are taken in fails here first.
+//
+//     cargo test -p lottery --test randomness
+
+mod common;
+
+use common::hex;
+use lottery::randomness_tools::{ fold_entropy, get_sha256_hashed_random, mix_entropy, RandomnessStream };
+
+// 0x00, 0x01, .., 0x1f
+fn seed() -> [u8; 32] {
+    core::array::from_fn(|i| i as u8)
+}
+
+#[test]
+fn stream_values() {
+    // block 0 gives the first 4 values, block 1 the next
+    let mut stream = RandomnessStream::new(seed());
+    let values: Vec<u64> = (0..6).map(|_| stream.next_u64()).collect();
+    assert_eq!(values, [
+        0xbd88_3a29_00e5_d6a9,
+        0x1fb7_7ad0_13e2_cb38,
+        0x012a_0752_8525_b28c,
+        0xd0f4_27e5_0bc4_f1bd,
+        0x3f3b_b7d8_2d47_ef04,
+        0x16ee_09a0_f309_3317,
+    ]);
+}
+
+#[test]
+fn uniform_below_values() {
+    let mut stream = RandomnessStream::new(seed());
+    assert_eq!(stream.uniform_below(10), 1);
+    assert_eq!(stream.uniform_below(3), 1);
+    assert_eq!(stream.uniform_below(1000), 460);
+    assert_eq!(stream.uniform_below(7), 0);
+    assert_eq!(stream.uniform_below(1), 0);
+}
+
+#[test]
+fn uniform_below_rejects_low_values() {
+    // the third value is below 2^64 % n and skipped, the fourth is taken
+    let n = (1u64 << 63) + 1;
+    let mut stream = RandomnessStream::new(seed());
+    stream.next_u64();
+    stream.next_u64();
+    assert_eq!(stream.uniform_below(n), 5_833_331_282_050_085_308);
+    assert_eq!(stream.next_u64(), 0x3f3b_b7d8_2d47_ef04);
+}
+
+#[test]
+fn partial_shuffle_values() {
+    let mut stream = RandomnessStream::new(seed());
+    let mut items: Vec<u32> = (0..10).collect();
+    assert_eq!(stream.partial_shuffle(&mut items, 4), [1, 8, 6, 3]);
+    assert_eq!(items, [1, 8, 6, 3, 4, 5, 2, 7, 0, 9]);
+}
+
+#[test]
+fn resumed_streams_continue_where_they_stopped() {
+    // a draw batch may stop anywhere in a block
+    for taken in 0..10 {
+        let mut stream = RandomnessStream::new(seed());
+        let values: Vec<u64> = (0..taken).map(|_| stream.next_u64()).collect();
+        assert_eq!(values.len() as u64, stream.position());
+        let mut resumed = RandomnessStream::resume(seed(), stream.position());
+        assert_eq!(resumed.position(), stream.position());
+        for _ in 0..6 {
+            assert_eq!(resumed.next_u64(), stream.next_u64(), "after {} values", taken);
+        }
+    }
+}
+
+#[test]
+fn seed_and_nonce_layout() {
+    // seed 1 and nonce 2, little endian, then 16 zero bytes
+    assert_eq!(get_sha256_hashed_random(1, 2), 0x0cd1_20b4_e7fe_c342);
+    assert_eq!(RandomnessStream::from_seed_and_nonce(1, 2).next_u64(), 0x0cd1_20b4_e7fe_c342);
+}
+
+#[test]
+fn entropy_layout() {
+    assert_eq!(
+        fold_entropy(&[0u8; 32], &[1u8; 32], &[2u8; 32]),
+        hex("934d1dbfb88c30da48404c96d98e39955ff1586d9382c2ceb15264cb23ea1710"),
+    );
+    assert_eq!(
+        mix_entropy(&[3u8; 32], &[4u8; 32]),
+        hex("505a9c6ac70bdffa46248e2025483f9fe997a0e31ed25559e448b73b7e02b9bd"),
+    );
+}
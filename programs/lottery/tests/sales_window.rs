@@ -0,0 +1,81 @@
+// When the ticket sales close, which is also when the draw may start, see Lottery::sales_closed,
+// and until when the draw may run, see Lottery::check_draw_window.
+//
+//     cargo test -p lottery --test sales_window
+
+use anchor_lang::prelude::*;
+use lottery::{ Lottery, LotteryStatus, RandomnessSource, MAX_ROUND_DURATION, MAX_SLOT_DRIFT_PERCENT, MS_PER_SLOT, REVEAL_WINDOW };
+
+const END: i64 = 1_000;
+const TARGET_SLOT: u64 = 500;
+
+fn lottery(randomness_source: RandomnessSource) -> Lottery {
+    Lottery { end: END, target_slot: TARGET_SLOT, randomness_source, ..Lottery::default() }
+}
+
+fn clock(unix_timestamp: i64, slot: u64) -> Clock {
+    Clock { slot, unix_timestamp, ..Clock::default() }
+}
+
+#[test]
+fn open_until_end_and_target_slot() {
+    for source in [RandomnessSource::SlotHash, RandomnessSource::CommitReveal, RandomnessSource::Oracle] {
+        let lottery = lottery(source.clone());
+        assert!(!lottery.sales_closed(&clock(END, TARGET_SLOT - 1)), "{:?}", source);
+        assert!(lottery.sales_closed(&clock(END + 1, TARGET_SLOT - 1)), "{:?}", source);
+    }
+}
+
+#[test]
+fn slot_hash_rounds_close_at_target_slot_before_end() {
+    // slots running faster than MS_PER_SLOT reach the target before `end`
+    for source in [RandomnessSource::SlotHash, RandomnessSource::CommitReveal] {
+        let lottery = lottery(source.clone());
+        assert!(lottery.sales_closed(&clock(END - 100, TARGET_SLOT)), "{:?}", source);
+        assert!(lottery.sales_closed(&clock(END - 100, TARGET_SLOT + 600)), "{:?}", source);
+    }
+}
+
+#[test]
+fn oracle_rounds_ignore_target_slot() {
+    let lottery = lottery(RandomnessSource::Oracle);
+    assert!(!lottery.sales_closed(&clock(END - 100, TARGET_SLOT + 600)));
+}
+
+// clock of a round created at slot 0 and time 0 when `slot` is produced, each slot taking `ms_per_slot`
+fn clock_at_slot(slot: u64, ms_per_slot: i64) -> Clock {
+    clock(slot as i64 * ms_per_slot / 1000, slot)
+}
+
+#[test]
+fn slow_slots_leave_the_whole_reveal_window() {
+    // the longest round, on slots as much slower than MS_PER_SLOT as a round may see
+    let ms_per_slot = MS_PER_SLOT * (100 + MAX_SLOT_DRIFT_PERCENT) / 100;
+    let mut lottery = Lottery { randomness_source: RandomnessSource::CommitReveal, status: LotteryStatus::Running, ..Lottery::default() };
+    lottery.schedule(&clock(0, 0), MAX_ROUND_DURATION);
+
+    // the target slot comes after `end`, still before the deadline set at creation
+    let reached = clock_at_slot(lottery.target_slot + 1, ms_per_slot);
+    assert!(reached.unix_timestamp > lottery.end);
+    assert!(reached.unix_timestamp <= lottery.reveal_deadline);
+    assert!(lottery.sales_closed(&reached));
+
+    // once the hash is saved, the secret can be revealed for a whole REVEAL_WINDOW
+    lottery.save_slot_hash([1u8; 32], reached.unix_timestamp);
+    let last = clock(reached.unix_timestamp + REVEAL_WINDOW, reached.slot + 1_000);
+    assert!(lottery.clone().check_draw_window(&last).unwrap());
+    let missed = clock(reached.unix_timestamp + REVEAL_WINDOW + 1, reached.slot + 1_000);
+    assert!(!lottery.check_draw_window(&missed).unwrap());
+    assert_eq!(lottery.status, LotteryStatus::Unresolved);
+}
+
+#[test]
+fn saving_the_hash_early_keeps_the_deadline() {
+    // fast slots reach the target before `end`
+    let mut lottery = lottery(RandomnessSource::SlotHash);
+    lottery.schedule(&clock(0, 0), 60 * 60);
+    let deadline = lottery.reveal_deadline;
+    assert_eq!(deadline, lottery.end + REVEAL_WINDOW);
+    lottery.save_slot_hash([1u8; 32], lottery.end - 100);
+    assert_eq!(lottery.reveal_deadline, deadline);
+}
@@ -0,0 +1,74 @@
+// Reading the draw's slot hash from the SlotHashes sysvar, see target_slot_hash.
+// Windows are built in the sysvar layout: u64 entry count, then (slot, hash) entries, newest first.
+//
+//     cargo test -p lottery --test slot_hashes
+
+use anchor_lang::prelude::*;
+use lottery::{ target_slot_hash, ErrCode };
+
+// sysvar data holding `slots`, newest first, each slot's hash filled with its low byte
+fn slot_hashes(slots: &[u64]) -> Vec<u8> {
+    let mut data = (slots.len() as u64).to_le_bytes().to_vec();
+    for &slot in slots.iter() {
+        data.extend_from_slice(&slot.to_le_bytes());
+        data.extend_from_slice(&hash_of(slot));
+    }
+    data
+}
+
+fn hash_of(slot: u64) -> [u8; 32] {
+    [slot as u8; 32]
+}
+
+// slots `newest` down to `oldest`, skipping `skipped`
+fn window(newest: u64, oldest: u64, skipped: &[u64]) -> Vec<u64> {
+    (oldest..=newest).rev().filter(|slot| !skipped.contains(slot)).collect()
+}
+
+#[test]
+fn target_not_reached() {
+    let data = slot_hashes(&window(100, 10, &[]));
+    assert_eq!(target_slot_hash(&data, 100, 100).unwrap_err(), error!(ErrCode::TargetSlotNotReached));
+    assert_eq!(target_slot_hash(&data, 100, 50).unwrap_err(), error!(ErrCode::TargetSlotNotReached));
+}
+
+#[test]
+fn target_in_window() {
+    let data = slot_hashes(&window(100, 10, &[]));
+    assert_eq!(target_slot_hash(&data, 50, 101).unwrap(), hash_of(50));
+    assert_eq!(target_slot_hash(&data, 100, 101).unwrap(), hash_of(100));
+}
+
+#[test]
+fn target_is_oldest_entry() {
+    let data = slot_hashes(&window(100, 10, &[]));
+    assert_eq!(target_slot_hash(&data, 10, 101).unwrap(), hash_of(10));
+}
+
+#[test]
+fn skipped_target_takes_next_produced_slot() {
+    let data = slot_hashes(&window(100, 10, &[50, 51]));
+    assert_eq!(target_slot_hash(&data, 50, 101).unwrap(), hash_of(52));
+    assert_eq!(target_slot_hash(&data, 51, 101).unwrap(), hash_of(52));
+}
+
+#[test]
+fn target_rotated_out() {
+    let data = slot_hashes(&window(100, 10, &[]));
+    assert_eq!(target_slot_hash(&data, 9, 101).unwrap_err(), error!(ErrCode::SlotHashExpired));
+    assert_eq!(target_slot_hash(&data, 0, 101).unwrap_err(), error!(ErrCode::SlotHashExpired));
+}
+
+#[test]
+fn skipped_target_before_oldest_entry_expired() {
+    // the target may have been skipped or rotated out, a later slot must not stand in for it
+    let data = slot_hashes(&window(100, 11, &[]));
+    assert_eq!(target_slot_hash(&data, 10, 101).unwrap_err(), error!(ErrCode::SlotHashExpired));
+}
+
+#[test]
+fn target_newer_than_window() {
+    // no entry at or after the target in the window yet
+    let data = slot_hashes(&window(100, 10, &[]));
+    assert_eq!(target_slot_hash(&data, 105, 106).unwrap_err(), error!(ErrCode::TargetSlotNotReached));
+}
@@ -0,0 +1,158 @@
+// Bookkeeping of the zero-copy ticket pool: buy_tickets takes tickets out of the unsold slots,
+// cancel_tickets puts them back, and the sold slots always hold exactly the tickets sold,
+// also once the raffle draw shuffled them.
+//
+//     cargo test -p lottery --test ticket_pool
+
+mod common;
+
+use common::pool;
+use lottery::randomness_tools::RandomnessStream;
+use lottery::{ TicketPool, MAX_TICKET_POOL_SPACE, MAX_TICKET_PRICE };
+
+const CAPACITY: u32 = 50;
+
+fn sorted(tickets: &[u32]) -> Vec<u32> {
+    let mut tickets = tickets.to_vec();
+    tickets.sort();
+    tickets
+}
+
+// every ticket must sit in exactly one slot, and find_slot must find the sold ones there
+fn assert_consistent(ticket_pool: &TicketPool, pool_tickets: &[u8]) {
+    let mut tickets: Vec<u32> = (0..CAPACITY as usize).map(|slot| TicketPool::ticket_at(pool_tickets, slot)).collect();
+    for (slot, &ticket) in tickets.iter().enumerate() {
+        let found = ticket_pool.find_slot(pool_tickets, ticket);
+        assert_eq!(found, Some(slot).filter(|&slot| slot >= ticket_pool.remaining as usize), "the pool lost track of ticket {}", ticket);
+    }
+    tickets.sort();
+    assert_eq!(tickets, (1..=CAPACITY).collect::<Vec<u32>>(), "a ticket is missing or held twice");
+}
+
+#[test]
+fn fresh_pool_holds_every_ticket_unsold() {
+    let (ticket_pool, pool_tickets) = pool(CAPACITY, 0);
+    assert_consistent(&ticket_pool, &pool_tickets);
+    assert!(ticket_pool.sold_tickets(&pool_tickets).is_empty());
+}
+
+#[test]
+fn taken_tickets_are_sold() {
+    let (mut ticket_pool, mut pool_tickets) = pool(CAPACITY, 0);
+    let mut stream = RandomnessStream::from_seed_and_nonce(1, 0);
+    let taken: Vec<u32> = (0..CAPACITY).map(|_| ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream)).collect();
+    assert_eq!(ticket_pool.remaining, 0);
+    assert_eq!(sorted(&taken), (1..=CAPACITY).collect::<Vec<u32>>(), "a ticket was assigned twice");
+    assert_eq!(sorted(&ticket_pool.sold_tickets(&pool_tickets)), sorted(&taken));
+    assert_consistent(&ticket_pool, &pool_tickets);
+}
+
+#[test]
+fn returned_tickets_can_be_sold_again() {
+    let (mut ticket_pool, mut pool_tickets) = pool(CAPACITY, 3);
+    let mut stream = RandomnessStream::from_seed_and_nonce(2, 0);
+    let taken: Vec<u32> = (0..20).map(|_| ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream)).collect();
+    for &ticket in taken.iter() {
+        ticket_pool.set_paid(&mut pool_tickets, ticket, 90);
+        ticket_pool.set_picks(&mut pool_tickets, ticket, &[1, 2, 3]);
+    }
+
+    // the first, a middle and the latest sold ticket
+    let returned = [taken[0], taken[9], taken[19]];
+    for &ticket in returned.iter() {
+        let slot = ticket_pool.find_slot(&pool_tickets, ticket).unwrap();
+        assert_eq!(ticket_pool.return_ticket(&mut pool_tickets, slot), ticket);
+        assert_consistent(&ticket_pool, &pool_tickets);
+    }
+    assert_eq!(ticket_pool.remaining, CAPACITY - 17);
+
+    let kept: Vec<u32> = taken.iter().copied().filter(|ticket| !returned.contains(ticket)).collect();
+    assert_eq!(sorted(&ticket_pool.sold_tickets(&pool_tickets)), sorted(&kept));
+    for &ticket in returned.iter() {
+        // a returned ticket keeps nothing of its last buyer
+        assert_eq!(ticket_pool.paid_for(&pool_tickets, ticket), 0);
+        assert_eq!(ticket_pool.picks_of(&pool_tickets, ticket), &[0, 0, 0]);
+    }
+    for &ticket in kept.iter() {
+        assert_eq!(ticket_pool.paid_for(&pool_tickets, ticket), 90);
+        assert_eq!(ticket_pool.picks_of(&pool_tickets, ticket), &[1, 2, 3]);
+    }
+
+    // the rest of the pool sells out, returned tickets included
+    let rest: Vec<u32> = (0..ticket_pool.remaining).map(|_| ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream)).collect();
+    for ticket in returned.iter() {
+        assert!(rest.contains(ticket), "returned ticket {} was not sold again", ticket);
+    }
+    assert_eq!(sorted(&ticket_pool.sold_tickets(&pool_tickets)), (1..=CAPACITY).collect::<Vec<u32>>());
+    assert_consistent(&ticket_pool, &pool_tickets);
+}
+
+#[test]
+#[should_panic(expected = "returned ticket was not sold")]
+fn unsold_tickets_cannot_be_returned() {
+    let (mut ticket_pool, mut pool_tickets) = pool(CAPACITY, 0);
+    let mut stream = RandomnessStream::from_seed_and_nonce(3, 0);
+    ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream);
+    ticket_pool.return_ticket(&mut pool_tickets, 0);
+}
+
+#[test]
+fn paid_amounts_up_to_the_highest_ticket_price_are_kept() {
+    let (mut ticket_pool, mut pool_tickets) = pool(CAPACITY, 0);
+    let mut stream = RandomnessStream::from_seed_and_nonce(4, 0);
+    let first = ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream);
+    let second = ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream);
+    ticket_pool.set_paid(&mut pool_tickets, first, MAX_TICKET_PRICE);
+    ticket_pool.set_paid(&mut pool_tickets, second, 1);
+    assert_eq!(ticket_pool.paid_for(&pool_tickets, first), MAX_TICKET_PRICE);
+    assert_eq!(ticket_pool.paid_for(&pool_tickets, second), 1);
+}
+
+#[test]
+fn the_largest_pools_fit_one_account() {
+    // one million raffle tickets, the size the lottery was redesigned for
+    assert!(TicketPool::max_capacity(0) >= 1_000_000);
+    for pick_count in 0..=10 {
+        let capacity = TicketPool::max_capacity(pick_count);
+        assert!(TicketPool::space(capacity, pick_count) <= MAX_TICKET_POOL_SPACE);
+        assert!(TicketPool::space(capacity + 1, pick_count) > MAX_TICKET_POOL_SPACE);
+    }
+    assert_eq!(TicketPool::max_capacity(0), 1_035_625);
+    assert_eq!(TicketPool::max_capacity(10), 521_028);
+}
+
+#[test]
+fn batched_draws_pick_the_tickets_of_one_batch() {
+    let (mut ticket_pool, mut pool_tickets) = pool(CAPACITY, 0);
+    let mut stream = RandomnessStream::from_seed_and_nonce(5, 0);
+    for _ in 0..CAPACITY - 3 {
+        ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream);
+    }
+    let before = pool_tickets.clone();
+    let seed = [6u8; 32];
+
+    let mut whole = before.clone();
+    let drawn = ticket_pool.draw_tickets(&mut whole, &mut RandomnessStream::new(seed), 0, 20);
+
+    // batches of 7, 7 and 6 tickets, each resuming the stream where the last one stopped
+    let mut batched = before.clone();
+    let mut position = 0;
+    let mut batches = Vec::new();
+    for (start, count) in [(0, 7), (7, 7), (14, 6)] {
+        let mut stream = RandomnessStream::resume(seed, position);
+        batches.extend(ticket_pool.draw_tickets(&mut batched, &mut stream, start, count));
+        position = stream.position();
+    }
+    assert_eq!(batches, drawn);
+    assert_eq!(batched, whole);
+
+    // the winners lead the sold slots in draw order, and no ticket was lost
+    let sold = ticket_pool.sold_tickets(&whole);
+    assert_eq!(&sold[..20], &drawn[..]);
+    assert_eq!(sorted(&sold), sorted(&ticket_pool.sold_tickets(&before)));
+    assert_consistent(&ticket_pool, &whole);
+
+    // verifiers get the order before the draw back
+    ticket_pool.undo_draw(&mut whole, &seed, 20);
+    assert_eq!(whole, before);
+}
@@ -0,0 +1,95 @@
+use anchor_lang::prelude::*;
+declare_id!("GDNkJobmp7EzMWGQJQ2DBCNdK4Xi4EZvbL6ytJDgtPZo");
+
+/*
+Stand-in for a VRF oracle so the lottery draw can be tested on a local validator.
+
+request_randomness => creates the result account for a requester (the lottery key)
+fulfill_randomness => the oracle authority writes the 32 random bytes
+
+The result account layout must stay in sync with `VrfResult` in the lottery program:
+[8 byte discriminator][requester: Pubkey][fulfilled: bool][randomness: [u8; 32]]
+*/
+
+#[program]
+pub mod mock_oracle {
+    use super::*;
+
+    /**
+     * Open a randomness request for the given requester
+     * @param ctx is the context of the program
+     * @return the result of the operation
+     */
+    pub fn request_randomness(ctx: Context<RequestRandomness>) -> Result<()> {
+        let result = &mut ctx.accounts.result;
+        result.requester = ctx.accounts.requester.key();
+        result.fulfilled = false;
+        result.randomness = [0u8; 32];
+        result.authority = ctx.accounts.authority.key();
+        result.bump = ctx.bumps.result;
+        Ok(())
+    }
+
+    /**
+     * Write the random bytes for an open request, only the request authority can do it
+     * @param ctx is the context of the program
+     * @param randomness is the value the lottery will consume
+     * @return the result of the operation
+     */
+    pub fn fulfill_randomness(ctx: Context<FulfillRandomness>, randomness: [u8; 32]) -> Result<()> {
+        let result = &mut ctx.accounts.result;
+        if result.fulfilled {
+            return err!(ErrCode::AlreadyFulfilled);
+        }
+        result.randomness = randomness;
+        result.fulfilled = true;
+        Ok(())
+    }
+}
+
+#[account]
+pub struct RandomnessResult {
+    pub requester: Pubkey,
+    pub fulfilled: bool,
+    pub randomness: [u8; 32],
+    pub authority: Pubkey,
+    bump: u8,
+}
+
+#[derive(Accounts)]
+pub struct RequestRandomness<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// CHECK: only used as the request seed
+    pub requester: AccountInfo<'info>,
+
+    #[account(
+        init,
+        payer = authority,
+        space = 8 + 32 + 1 + 32 + 32 + 1,
+        seeds = [b"randomness", requester.key().as_ref()],
+        bump
+    )]
+    pub result: Account<'info, RandomnessResult>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct FulfillRandomness<'info> {
+    pub authority: Signer<'info>,
+
+    #[account(
+        mut,
+        has_one = authority,
+        seeds = [b"randomness", result.requester.as_ref()],
+        bump = result.bump
+    )]
+    pub result: Account<'info, RandomnessResult>,
+}
+
+#[error_code]
+pub enum ErrCode {
+    #[msg("Randomness already fulfilled")]
+    AlreadyFulfilled,
+}
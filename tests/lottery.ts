@@ -1,11 +1,13 @@
 import * as anchor from "@coral-xyz/anchor";
 import { Program } from "@coral-xyz/anchor";
 import { Lottery } from "../target/types/lottery";
-import { PublicKey, LAMPORTS_PER_SOL, TransactionMessage, VersionedTransaction } from '@solana/web3.js';
+import { MockOracle } from "../target/types/mock_oracle";
+import { ComputeBudgetProgram, PublicKey, LAMPORTS_PER_SOL, TransactionMessage, VersionedTransaction } from '@solana/web3.js';
 import { BN } from "bn.js";
 import NodeWallet from "@coral-xyz/anchor/dist/cjs/nodewallet";
-import { Account, ASSOCIATED_TOKEN_PROGRAM_ID, createAssociatedTokenAccountIdempotent, createAssociatedTokenAccountIdempotentInstruction, createMint, getOrCreateAssociatedTokenAccount, mintTo, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID } from "@solana/spl-token";
+import { Account, ASSOCIATED_TOKEN_PROGRAM_ID, createAssociatedTokenAccountIdempotent, createAssociatedTokenAccountIdempotentInstruction, createMint, getAssociatedTokenAddressSync, getMint, getOrCreateAssociatedTokenAccount, mintTo, TOKEN_2022_PROGRAM_ID, TOKEN_PROGRAM_ID } from "@solana/spl-token";
 import { expect } from "chai";
+import { createHash, randomBytes } from "crypto";
 
 function sleep(ms: number) {
   return new Promise(resolve => setTimeout(resolve, ms))
@@ -19,6 +21,7 @@ describe("Lottery", () => {
   anchor.setProvider(provider);
   const connection = provider.connection;
   const program = anchor.workspace.Lottery as Program<Lottery>;
+  const oracleProgram = anchor.workspace.MockOracle as Program<MockOracle>;
 
   const owner = provider.wallet as NodeWallet;
   const users = Array.from({ length: 10 }, () => anchor.web3.Keypair.generate());
@@ -38,6 +41,188 @@ describe("Lottery", () => {
   let ownerAta: Account
   let feeAccountAta: Account
 
+  // operator secret for commit-reveal, only sha256(secret) goes on chain at creation
+  const secret = randomBytes(32);
+  const secretCommitment = Array.from(createHash("sha256").update(secret).digest());
+
+  // discriminator, lottery, capacity, remaining, pick count
+  const ticketPoolHeaderSize = 8 + 32 + 4 + 4 + 4;
+  const ticketPrice = new BN(10 * Math.pow(10, 9)); // 10 tokens
+  const feePercent = 1;
+
+  type Round = {
+    creator: anchor.web3.Keypair,
+    appStats: PublicKey,
+    lottery: PublicKey,
+    ticketPool: PublicKey,
+    ticketAmount: number,
+    prize: PublicKey,
+    proceeds: PublicKey,
+  }
+
+  type RoundOptions = {
+    // an operator of its own by default
+    creator?: anchor.web3.Keypair,
+    ticketPrice?: BN,
+    ticketAmount?: number,
+    // seconds the round sells tickets, a day by default
+    roundDuration?: number,
+    maxTicketsPerBuyer?: number,
+    nftTickets?: boolean,
+    priceTiers?: { tickets: number, price: BN }[],
+    allowlistRoot?: number[],
+    gateMint?: PublicKey,
+    gateMinBalance?: BN,
+    cancelPenaltyPercent?: number,
+    // prize token account of the creator receiving sweep_rollover, none by default
+    rolloverToken?: PublicKey,
+    // bytes of the ticket pool, TicketPool::space of the round by default
+    ticketPoolSpace?: number,
+  }
+
+  // allowlist entry and gate token of the wallet getting the tickets
+  type Entry = {
+    proof?: number[][],
+    allowance?: number,
+    gateToken?: PublicKey,
+  }
+
+  // round shared by the tests below, users 0 to 4 hold two tickets each once bought
+  let round: Round;
+
+  function pda(...seeds: Buffer[]): [PublicKey, number] {
+    return PublicKey.findProgramAddressSync(seeds, program.programId);
+  }
+
+  function u32le(value: number): Buffer {
+    const bytes = Buffer.alloc(4);
+    bytes.writeUInt32LE(value);
+    return bytes;
+  }
+
+  const appStatsOf = (operator: PublicKey) => pda(Buffer.from("app-stats"), operator.toBuffer());
+  const receiptOf = (round: Round, wallet: PublicKey) => pda(Buffer.from("tickets"), round.lottery.toBuffer(), wallet.toBuffer())[0];
+  const listingOf = (round: Round, ticket: number) => pda(Buffer.from("listing"), round.lottery.toBuffer(), u32le(ticket))[0];
+
+  async function tokenBalance(address: PublicKey): Promise<BN> {
+    return new BN((await connection.getTokenAccountBalance(address)).value.amount);
+  }
+
+  async function expectError(transaction: Promise<unknown>, code: string) {
+    try {
+      await transaction;
+    } catch (error) {
+      expect((error as anchor.AnchorError).error.errorCode.code).to.be.equal(code);
+      return;
+    }
+    expect.fail(`expected ${code}`);
+  }
+
+  async function newOperator(): Promise<anchor.web3.Keypair> {
+    const operator = anchor.web3.Keypair.generate();
+    await airdropSol(connection, operator.publicKey);
+    const [operatorAppStats, bump] = appStatsOf(operator.publicKey);
+    await program.methods.createAppStats(feePercent, bump).accounts({
+      signer: operator.publicKey,
+      adminAccount: adminAccount.publicKey,
+      feeAccount: feeAccount.publicKey,
+      mint,
+      appStats: operatorAppStats,
+    }).signers([operator]).rpc();
+    return operator;
+  }
+
+  // a commit-reveal raffle, the ticket pool is allocated in the same transaction
+  async function createRound(options: RoundOptions = {}): Promise<Round> {
+    const creator = options.creator ?? await newOperator();
+    const ticketAmount = options.ticketAmount ?? 100;
+    const lottery = anchor.web3.Keypair.generate();
+    const ticketPool = anchor.web3.Keypair.generate();
+    const [prize, prizeBump] = pda(Buffer.from("prize"), lottery.publicKey.toBuffer());
+    const [proceeds, proceedsBump] = pda(Buffer.from("proceeds"), lottery.publicKey.toBuffer());
+    const appStats = appStatsOf(creator.publicKey)[0];
+
+    // the ticket pool is allocated here, it can be larger than the program could allocate itself
+    // ticket slots, the winner bitmap and what each ticket paid in 6 bytes
+    const ticketPoolSpace = options.ticketPoolSpace ?? ticketPoolHeaderSize + 10 * ticketAmount + Math.ceil(ticketAmount / 8);
+    const createPool = anchor.web3.SystemProgram.createAccount({
+      fromPubkey: creator.publicKey,
+      newAccountPubkey: ticketPool.publicKey,
+      space: ticketPoolSpace,
+      lamports: await connection.getMinimumBalanceForRentExemption(ticketPoolSpace),
+      programId: program.programId
+    });
+
+    await program.methods.createLottery(
+      options.ticketPrice ?? ticketPrice,
+      ticketAmount,
+      new BN(options.roundDuration ?? 60 * 60 * 24),
+      options.maxTicketsPerBuyer ?? 0,
+      prizeBump,
+      proceedsBump,
+      secretCommitment,
+      options.nftTickets ?? false,
+      { raffle: {} },
+      0,
+      0,
+      [],
+      options.priceTiers ?? [],
+      options.allowlistRoot ?? Array(32).fill(0),
+      options.gateMint ?? PublicKey.default,
+      options.gateMinBalance ?? new BN(0),
+      options.cancelPenaltyPercent ?? 0
+    ).accounts({
+      signer: creator.publicKey,
+      lottery: lottery.publicKey,
+      ticketPool: ticketPool.publicKey,
+      prize,
+      proceeds,
+      rolloverToken: options.rolloverToken ?? null,
+      mint,
+      appStats,
+      adminAccount: adminAccount.publicKey
+    }).preInstructions([createPool]).signers([creator, lottery, ticketPool]).rpc();
+
+    return { creator, appStats, lottery: lottery.publicKey, ticketPool: ticketPool.publicKey, ticketAmount, prize, proceeds };
+  }
+
+  // accounts of buy_tickets and buy_tickets_for, user `buyer` pays
+  function sale(round: Round, buyer: number, gateToken?: PublicKey) {
+    return {
+      lottery: round.lottery,
+      ticketPool: round.ticketPool,
+      signer: users[buyer].publicKey,
+      proceeds: round.proceeds,
+      appStats: round.appStats,
+      creatorToken: usersAtas[buyer].address,
+      prize: round.prize,
+      feeAccount: feeAccount.publicKey,
+      feeToken: feeAccountAta.address,
+      referrerStats: null,
+      referralVault: null,
+      gateToken: gateToken ?? null,
+      owner: round.creator.publicKey
+    };
+  }
+
+  function buyTickets(round: Round, buyer: number, ticketAmount: number, entry: Entry = {}) {
+    return program.methods.buyTickets(
+      new BN(ticketAmount),
+      Array.from(randomBytes(32)),
+      Buffer.from([]),
+      entry.proof ?? [],
+      entry.allowance ?? 0
+    ).accounts({
+      sale: sale(round, buyer, entry.gateToken),
+      receipt: receiptOf(round, users[buyer].publicKey)
+    }).signers([users[buyer]]).rpc();
+  }
+
+  async function ticketsOf(round: Round, wallet: PublicKey): Promise<number[]> {
+    const receipt = await program.account.ticketReceipt.fetch(receiptOf(round, wallet));
+    return Array.from(receipt.tickets);
+  }
+
 
   it("Should airdrop sol", async () => {
     await airdropSol(provider.connection, feeAccount.publicKey);
@@ -112,7 +297,6 @@ describe("Lottery", () => {
 
 
   it("initalize", async () => {
-    const fee_percent = 1;
     let [appStats, bump] = PublicKey.findProgramAddressSync(
       [
         anchor.utils.bytes.utf8.encode('app-stats'),
@@ -122,11 +306,12 @@ describe("Lottery", () => {
     );
 
     await program.methods.createAppStats(
-      fee_percent,
+      feePercent,
       bump
     ).accounts({
       appStats,
       mint,
+      adminAccount: adminAccount.publicKey,
       feeAccount: feeAccount.publicKey
     }).rpc();
 
@@ -147,74 +332,825 @@ describe("Lottery", () => {
 
 
   it("Create competition", async () => {
-    try {
+    round = await createRound({ creator: owner.payer });
+
+    const lotteryInfo = await program.account.lottery.fetch(round.lottery);
+    expect(lotteryInfo.status).to.deep.equal({ running: {} });
+    expect(lotteryInfo.randomnessSource).to.deep.equal({ commitReveal: {} });
+    expect(Array.from(lotteryInfo.secretCommitment)).to.deep.equal(secretCommitment);
+    expect(lotteryInfo.ticketPool.toBase58()).to.be.equal(round.ticketPool.toBase58());
+    expect(lotteryInfo.ticketAmount).to.be.equal(100);
+    expect(lotteryInfo.ticketsSold).to.be.equal(0);
+
+    const poolInfo = await program.account.ticketPool.fetch(round.ticketPool);
+    expect(poolInfo.lottery.toBase58()).to.be.equal(round.lottery.toBase58());
+    expect(poolInfo.capacity).to.be.equal(100);
+    expect(poolInfo.remaining).to.be.equal(100);
+  });
 
-      const ticketPrice = new BN(10 * Math.pow(10, 9)); // 10 tokens
-      //console.log(ticketPrice.toString());
-      //const ticketPrice = new BN(1);
-      const ticketAmount = 100;
-      //console.log(prizeAmount.toString());
+  it("Buy tickets", async () => {
+    const feeBefore = await tokenBalance(feeAccountAta.address);
+    for (let i = 0; i < 5; i++) {
+      const before = await tokenBalance(usersAtas[i].address);
+      await buyTickets(round, i, 2);
+      const after = await tokenBalance(usersAtas[i].address);
+      expect(before.sub(after).toString()).to.be.equal(ticketPrice.muln(2).toString());
+
+      const receipt = await program.account.ticketReceipt.fetch(receiptOf(round, users[i].publicKey));
+      expect(receipt.owner.toBase58()).to.be.equal(users[i].publicKey.toBase58());
+      expect(receipt.tickets.length).to.be.equal(2);
+    }
 
-      const [prize, prize_bump] = PublicKey.findProgramAddressSync(
-        [anchor.utils.bytes.utf8.encode("prize")],
-        program.programId
-      );
+    // the fee goes to the fee account, the rest into the prize
+    const sold = ticketPrice.muln(10);
+    const fee = sold.muln(feePercent).divn(100);
+    const feeAfter = await tokenBalance(feeAccountAta.address);
+    expect(feeAfter.sub(feeBefore).toString()).to.be.equal(fee.toString());
+    expect((await tokenBalance(round.prize)).toString()).to.be.equal(sold.sub(fee).toString());
+
+    const lotteryInfo = await program.account.lottery.fetch(round.lottery);
+    expect(lotteryInfo.ticketsSold).to.be.equal(10);
+    expect(lotteryInfo.collected.toString()).to.be.equal(sold.sub(fee).toString());
+    const poolInfo = await program.account.ticketPool.fetch(round.ticketPool);
+    expect(poolInfo.remaining).to.be.equal(90);
+
+    // every ticket is held by exactly one buyer
+    const held = new Set<number>();
+    for (let i = 0; i < 5; i++) {
+      for (const ticket of await ticketsOf(round, users[i].publicKey)) {
+        expect(ticket).to.be.within(1, 100);
+        held.add(ticket);
+      }
+    }
+    expect(held.size).to.be.equal(10);
+  });
 
-      const [proceeds, proceeds_bump] = PublicKey.findProgramAddressSync(
-        [anchor.utils.bytes.utf8.encode("proceeds")],
-        program.programId
-      );
+  it("Should not sell more tickets than are left", async () => {
+    await expectError(buyTickets(round, 5, 91), "InvalidArgus");
+  });
 
-      const [appStats, bump] = PublicKey.findProgramAddressSync(
-        [
-          anchor.utils.bytes.utf8.encode('app-stats'),
-          owner.publicKey.toBuffer(),
-        ],
-        program.programId
-      );
+  it("Should not sell more than MAX_PURCHASE_TICKETS tickets at once", async () => {
+    const largeRound = await createRound({ ticketAmount: 200 });
+    await expectError(buyTickets(largeRound, 5, 101), "PurchaseTooLarge");
+    // the largest purchase asks for more than the default compute limit
+    await program.methods.buyTickets(new BN(100), Array.from(randomBytes(32)), Buffer.from([]), [], 0).accounts({
+      sale: sale(largeRound, 5),
+      receipt: receiptOf(largeRound, users[5].publicKey)
+    }).preInstructions([ComputeBudgetProgram.setComputeUnitLimit({ units: 1_400_000 })]).signers([users[5]]).rpc();
+    expect(await ticketsOf(largeRound, users[5].publicKey)).to.have.lengthOf(100);
+  });
 
-      const ixn1 = await program.methods.createLottery(
-        ticketPrice,
-        ticketAmount,
-        prize_bump,
-        proceeds_bump
-      ).accounts({
-        //lottery: lotteryAccount.publicKey,
-        mint,
-        prize,
-        //proceeds,
-        appStats
-      }).signers([
-        owner.payer
-      ]).instruction();
 
-      const { blockhash } = await connection.getLatestBlockhash();
+  it("Should charge the cheapest combination of bundles", async () => {
+    const tokens = (amount: number) => new BN(amount).mul(new BN(10).pow(new BN(9)));
+    const bundleRound = await createRound({
+      priceTiers: [{ tickets: 5, price: tokens(45) }, { tickets: 10, price: tokens(80) }]
+    });
 
-      const message = new TransactionMessage({
-        payerKey: owner.publicKey,
-        recentBlockhash: blockhash,
-        instructions: [ixn1]
-      }).compileToV0Message();
+    // 5 + 2 singles, then 10 + 5
+    for (const [ticketAmount, price] of [[7, 65], [15, 125]]) {
+      const before = await tokenBalance(usersAtas[9].address);
+      await buyTickets(bundleRound, 9, ticketAmount);
+      const after = await tokenBalance(usersAtas[9].address);
+      expect(before.sub(after).toString()).to.be.equal(tokens(price).toString());
+    }
 
-      const transaction = new VersionedTransaction(message);
+    const lotteryInfo = await program.account.lottery.fetch(bundleRound.lottery);
+    expect(lotteryInfo.ticketsSold).to.be.equal(22);
+    expect(lotteryInfo.collected.toString()).to.be.equal(tokens(190).muln(100 - feePercent).divn(100).toString());
+  });
 
-      transaction.sign([owner.payer]);
+  it("Should only send rollovers to a prize token account of the creator", async () => {
+    const operator = await newOperator();
+    await expectError(createRound({ creator: operator, rolloverToken: usersAtas[0].address }), "InvalidRolloverAccount");
 
-      await provider.connection.confirmTransaction(
-        await provider.connection.sendRawTransaction(transaction.serialize())
-      );
+    const operatorToken = await getOrCreateAssociatedTokenAccount(connection, owner.payer, mint, operator.publicKey);
+    const rolloverRound = await createRound({ creator: operator, rolloverToken: operatorToken.address });
+    const lotteryInfo = await program.account.lottery.fetch(rolloverRound.lottery);
+    expect(lotteryInfo.rolloverAccount.toBase58()).to.be.equal(operatorToken.address.toBase58());
+  });
 
-    } catch (error) {
-      console.error(error);
-      // if (error instanceof SendTransactionError) {
-      //   const logs = error.getLogs(connection);
-      //   console.log("Transaction Error Logs:", logs);
-      // } else {
-      //   console.error("An unexpected error occurred:", error);
-      // }
+  it("Should reject bundles that cost as much as single tickets", async () => {
+    await expectError(
+      createRound({ priceTiers: [{ tickets: 5, price: ticketPrice.muln(5) }] }),
+      "InvalidPriceTiers"
+    );
+    // bundles larger than the round or than MAX_BUNDLE_TICKETS
+    await expectError(
+      createRound({ ticketAmount: 20, priceTiers: [{ tickets: 21, price: ticketPrice }] }),
+      "InvalidPriceTiers"
+    );
+    await expectError(
+      createRound({ ticketAmount: 200, priceTiers: [{ tickets: 101, price: ticketPrice }] }),
+      "InvalidPriceTiers"
+    );
+  });
+
+  it("Should reject ticket prices the ticket pool can't refund", async () => {
+    // what a ticket paid is kept in 6 bytes, MAX_TICKET_PRICE is 2^48 - 1
+    await expectError(createRound({ ticketPrice: new BN(2).pow(new BN(48)) }), "InvalidTicketPrice");
+  });
+
+  it("Should reject rounds larger than the biggest ticket pool", async () => {
+    // TicketPool::max_capacity(0) + 1, the system program can't allocate its pool
+    await expectError(createRound({ ticketAmount: 1_035_626, ticketPoolSpace: 1024 }), "InvalidTicketAmount");
+  });
+
+  function buyTicketsFor(round: Round, buyer: number, beneficiary: PublicKey, ticketAmount: number, memo: string | null) {
+    return program.methods.buyTicketsFor(
+      beneficiary,
+      new BN(ticketAmount),
+      null,
+      Buffer.from([]),
+      memo,
+      [],
+      0
+    ).accounts({
+      sale: sale(round, buyer),
+      receipt: receiptOf(round, beneficiary)
+    }).signers([users[buyer]]).rpc();
+  }
+
+  it("Buy tickets for another wallet", async () => {
+    const before = await tokenBalance(usersAtas[5].address);
+    await buyTicketsFor(round, 5, users[6].publicKey, 3, "happy birthday");
+    const after = await tokenBalance(usersAtas[5].address);
+    expect(before.sub(after).toString()).to.be.equal(ticketPrice.muln(3).toString());
+
+    // the beneficiary holds the tickets, the payer gets no receipt
+    const receipt = await program.account.ticketReceipt.fetch(receiptOf(round, users[6].publicKey));
+    expect(receipt.owner.toBase58()).to.be.equal(users[6].publicKey.toBase58());
+    expect(receipt.tickets.length).to.be.equal(3);
+    expect(await connection.getAccountInfo(receiptOf(round, users[5].publicKey))).to.be.null;
+  });
+
+  it("Should not gift tickets to yourself or with a long memo", async () => {
+    await expectError(buyTicketsFor(round, 5, users[5].publicKey, 1, null), "InvalidArgus");
+    await expectError(buyTicketsFor(round, 5, users[6].publicKey, 1, "x".repeat(201)), "MemoTooLong");
+  });
+
+  // what a sold ticket paid into the prize account, after the slots and the winner bitmap of a raffle pool
+  async function paidFor(round: Round, ticket: number): Promise<BN> {
+    const data = (await connection.getAccountInfo(round.ticketPool)).data;
+    const offset = ticketPoolHeaderSize + 4 * round.ticketAmount + Math.ceil(round.ticketAmount / 8) + 6 * (ticket - 1);
+    return new BN(data.subarray(offset, offset + 6), "le");
+  }
+
+  // whether the draw picked a raffle ticket, the winner bitmap follows the ticket slots
+  async function isWinner(round: Round, ticket: number): Promise<boolean> {
+    const data = (await connection.getAccountInfo(round.ticketPool)).data;
+    const offset = ticketPoolHeaderSize + 4 * round.ticketAmount + Math.floor((ticket - 1) / 8);
+    return (data[offset] & (1 << ((ticket - 1) % 8))) != 0;
+  }
+
+  // pool slot of a sold ticket, the slots follow the header as u32 and zero stands for ticket `slot + 1`
+  async function slotOf(round: Round, ticket: number): Promise<number> {
+    const data = (await connection.getAccountInfo(round.ticketPool)).data;
+    const pool = await program.account.ticketPool.fetch(round.ticketPool);
+    for (let slot = pool.remaining; slot < round.ticketAmount; slot++) {
+      const held = data.readUInt32LE(ticketPoolHeaderSize + 4 * slot);
+      if ((held == 0 ? slot + 1 : held) == ticket) {
+        return slot;
+      }
+    }
+    throw new Error(`ticket ${ticket} is not sold`);
+  }
+
+  // runs continue_draw until the draw started by reveal_winners or consume_randomness ends
+  async function finishDraw(round: Round, drawRecord: PublicKey) {
+    while ("drawing" in (await program.account.lottery.fetch(round.lottery)).status) {
+      await program.methods.continueDraw().accounts({
+        lottery: round.lottery,
+        ticketPool: round.ticketPool,
+        drawRecord
+      }).rpc();
+    }
+  }
+
+  function claimPrize(round: Round, holder: number) {
+    return program.methods.claimPrize().accounts({
+      lottery: round.lottery,
+      ticketPool: round.ticketPool,
+      receipt: receiptOf(round, users[holder].publicKey),
+      user: users[holder].publicKey,
+      userToken: usersAtas[holder].address,
+      prize: round.prize,
+      mint
+    }).signers([users[holder]]).rpc();
+  }
+
+  // waits until the cluster clock is past `timestamp`
+  async function waitPast(timestamp: number) {
+    const now = async () => Number((await connection.getAccountInfo(anchor.web3.SYSVAR_CLOCK_PUBKEY)).data.readBigInt64LE(32));
+    while (await now() <= timestamp) {
+      await sleep(500);
+    }
+  }
+
+  // `operator` names the app stats whose fee account receives the penalty, the round creator's by default,
+  // `slots` are where the tickets sit in the pool, looked up by default
+  async function cancelTickets(round: Round, holder: number, tickets: number[], operator: PublicKey = round.creator.publicKey, slots?: number[]) {
+    slots = slots ?? await Promise.all(tickets.map((ticket) => slotOf(round, ticket)));
+    return program.methods.cancelTickets(tickets, slots).accounts({
+      lottery: round.lottery,
+      ticketPool: round.ticketPool,
+      receipt: receiptOf(round, users[holder].publicKey),
+      signer: users[holder].publicKey,
+      userToken: usersAtas[holder].address,
+      prize: round.prize,
+      feeToken: feeAccountAta.address,
+      appStats: appStatsOf(operator)[0],
+      owner: operator
+    }).signers([users[holder]]).rpc();
+  }
+
+  it("Cancel tickets for what they paid less the penalty", async () => {
+    const bundlePrice = ticketPrice.muln(9).divn(2);
+    const cancelRound = await createRound({
+      priceTiers: [{ tickets: 5, price: bundlePrice }],
+      cancelPenaltyPercent: 10
+    });
+    await buyTickets(cancelRound, 7, 5);
+
+    // bundle tickets paid their share of the bundle, not ticket_price
+    const tickets = await ticketsOf(cancelRound, users[7].publicKey);
+    const paid = bundlePrice.muln(100 - feePercent).divn(100).divn(5);
+    for (const ticket of tickets) {
+      expect((await paidFor(cancelRound, ticket)).toString()).to.be.equal(paid.toString());
     }
+
+    const cancelled = tickets.slice(0, 2);
+    const refund = paid.muln(2);
+    const penalty = refund.muln(10).divn(100);
+    const userBefore = await tokenBalance(usersAtas[7].address);
+    const feeBefore = await tokenBalance(feeAccountAta.address);
+    await cancelTickets(cancelRound, 7, cancelled);
+    const userAfter = await tokenBalance(usersAtas[7].address);
+    const feeAfter = await tokenBalance(feeAccountAta.address);
+    expect(userAfter.sub(userBefore).toString()).to.be.equal(refund.sub(penalty).toString());
+    expect(feeAfter.sub(feeBefore).toString()).to.be.equal(penalty.toString());
+
+    // the tickets are back in the pool and can be sold again
+    expect(await ticketsOf(cancelRound, users[7].publicKey)).to.have.members(tickets.slice(2));
+    const lotteryInfo = await program.account.lottery.fetch(cancelRound.lottery);
+    expect(lotteryInfo.ticketsSold).to.be.equal(3);
+    expect(lotteryInfo.collected.toString()).to.be.equal(paid.muln(3).toString());
+    const poolInfo = await program.account.ticketPool.fetch(cancelRound.ticketPool);
+    expect(poolInfo.remaining).to.be.equal(97);
+    for (const ticket of cancelled) {
+      expect((await paidFor(cancelRound, ticket)).toString()).to.be.equal("0");
+    }
+  });
+
+  it("Should not cancel tickets of another wallet", async () => {
+    const [ticket] = await ticketsOf(round, users[0].publicKey);
+    await expectError(cancelTickets(round, 1, [ticket]), "InvalidBuyer");
+  });
+
+  it("Should not cancel tickets from another slot", async () => {
+    const [ticket, other] = await ticketsOf(round, users[0].publicKey);
+    await expectError(cancelTickets(round, 0, [ticket], round.creator.publicKey, [await slotOf(round, other)]), "InvalidArgus");
+    // unsold slots can't be returned either
+    await expectError(cancelTickets(round, 0, [ticket], round.creator.publicKey, [0]), "InvalidArgus");
+  });
+
+  it("Should only pay the cancellation penalty to the round creator's fee account", async () => {
+    // app stats of another operator could name any fee account, or one its holder controls
+    const other = await newOperator();
+    const [ticket] = await ticketsOf(round, users[0].publicKey);
+    await expectError(cancelTickets(round, 0, [ticket], other.publicKey), "ConstraintRaw");
+    expect(await ticketsOf(round, users[0].publicKey)).to.include(ticket);
+  });
+
+  function transferTicket(round: Round, holder: number, ticket: number, newOwner: PublicKey, entry: Entry = {}) {
+    return program.methods.transferTicket(ticket, newOwner, entry.proof ?? [], entry.allowance ?? 0).accounts({
+      lottery: round.lottery,
+      receipt: receiptOf(round, users[holder].publicKey),
+      newOwnerReceipt: receiptOf(round, newOwner),
+      gateToken: entry.gateToken ?? null,
+      signer: users[holder].publicKey
+    }).signers([users[holder]]).rpc();
+  }
+
+  it("Transfer a ticket", async () => {
+    const [ticket, kept] = await ticketsOf(round, users[0].publicKey);
+    await transferTicket(round, 0, ticket, users[7].publicKey);
+
+    expect(await ticketsOf(round, users[0].publicKey)).to.deep.equal([kept]);
+    const receipt = await program.account.ticketReceipt.fetch(receiptOf(round, users[7].publicKey));
+    expect(receipt.owner.toBase58()).to.be.equal(users[7].publicKey.toBase58());
+    expect(Array.from(receipt.tickets)).to.deep.equal([ticket]);
+  });
+
+  it("Should not transfer a ticket to yourself or one you don't hold", async () => {
+    const [ticket] = await ticketsOf(round, users[0].publicKey);
+    await expectError(transferTicket(round, 0, ticket, users[0].publicKey), "InvalidArgus");
+    await expectError(transferTicket(round, 1, ticket, users[8].publicKey), "InvalidBuyer");
+  });
+
+  function listTicket(round: Round, seller: number, ticket: number, price: BN) {
+    return program.methods.listTicket(ticket, price).accounts({
+      lottery: round.lottery,
+      receipt: receiptOf(round, users[seller].publicKey),
+      listing: listingOf(round, ticket),
+      seller: users[seller].publicKey
+    }).signers([users[seller]]).rpc();
+  }
+
+  function cancelListing(round: Round, seller: number, ticket: number, signer: number) {
+    return program.methods.cancelListing().accounts({
+      lottery: round.lottery,
+      listing: listingOf(round, ticket),
+      receipt: receiptOf(round, users[seller].publicKey),
+      seller: users[seller].publicKey,
+      signer: users[signer].publicKey
+    }).signers([users[signer]]).rpc();
+  }
+
+  // `operator` names the app stats setting the royalty, the round creator's by default
+  function buyListedTicket(round: Round, seller: number, ticket: number, buyer: number, entry: Entry = {}, operator: PublicKey = round.creator.publicKey) {
+    return program.methods.buyListedTicket(entry.proof ?? [], entry.allowance ?? 0).accounts({
+      lottery: round.lottery,
+      listing: listingOf(round, ticket),
+      sellerReceipt: receiptOf(round, users[seller].publicKey),
+      buyerReceipt: receiptOf(round, users[buyer].publicKey),
+      signer: users[buyer].publicKey,
+      buyerToken: usersAtas[buyer].address,
+      sellerToken: usersAtas[seller].address,
+      feeToken: feeAccountAta.address,
+      gateToken: entry.gateToken ?? null,
+      seller: users[seller].publicKey,
+      appStats: appStatsOf(operator)[0],
+      owner: operator
+    }).signers([users[buyer]]).rpc();
+  }
+
+  it("List a ticket and take it off sale", async () => {
+    const [ticket, kept] = await ticketsOf(round, users[1].publicKey);
+    await listTicket(round, 1, ticket, ticketPrice);
+
+    // the listing holds the ticket, it still counts against the seller's cap
+    let receipt = await program.account.ticketReceipt.fetch(receiptOf(round, users[1].publicKey));
+    expect(Array.from(receipt.tickets)).to.deep.equal([kept]);
+    expect(receipt.listedTickets).to.be.equal(1);
+    const listing = await program.account.ticketListing.fetch(listingOf(round, ticket));
+    expect(listing.seller.toBase58()).to.be.equal(users[1].publicKey.toBase58());
+    expect(listing.ticketNumber).to.be.equal(ticket);
+    expect(listing.price.toString()).to.be.equal(ticketPrice.toString());
+
+    // only the seller can cancel while the round is running
+    await expectError(cancelListing(round, 1, ticket, 2), "InvalidSeller");
+    await cancelListing(round, 1, ticket, 1);
+
+    receipt = await program.account.ticketReceipt.fetch(receiptOf(round, users[1].publicKey));
+    expect(Array.from(receipt.tickets)).to.have.members([ticket, kept]);
+    expect(receipt.listedTickets).to.be.equal(0);
+    expect(await connection.getAccountInfo(listingOf(round, ticket))).to.be.null;
   });
 
+  it("Should only list tickets at prices the royalty can be taken from", async () => {
+    const [ticket] = await ticketsOf(round, users[1].publicKey);
+    // MAX_TICKET_PRICE, 2^48 - 1
+    const highestPrice = new BN(2).pow(new BN(48)).subn(1);
+    await expectError(listTicket(round, 1, ticket, highestPrice.addn(1)), "InvalidTicketPrice");
+    await expectError(listTicket(round, 1, ticket, new BN(0)), "InvalidTicketPrice");
+
+    await listTicket(round, 1, ticket, highestPrice);
+    const listing = await program.account.ticketListing.fetch(listingOf(round, ticket));
+    expect(listing.price.toString()).to.be.equal(highestPrice.toString());
+    await cancelListing(round, 1, ticket, 1);
+  });
+
+  it("Buy a listed ticket", async () => {
+    const [ticket, kept] = await ticketsOf(round, users[1].publicKey);
+    const price = ticketPrice.muln(2);
+    await listTicket(round, 1, ticket, price);
+    await expectError(buyListedTicket(round, 1, ticket, 1), "InvalidArgus");
+
+    const sellerBefore = await tokenBalance(usersAtas[1].address);
+    const buyerBefore = await tokenBalance(usersAtas[8].address);
+    await buyListedTicket(round, 1, ticket, 8);
+    const sellerAfter = await tokenBalance(usersAtas[1].address);
+    const buyerAfter = await tokenBalance(usersAtas[8].address);
+
+    // no royalty is configured, the seller gets the whole price
+    expect(buyerBefore.sub(buyerAfter).toString()).to.be.equal(price.toString());
+    expect(sellerAfter.sub(sellerBefore).toString()).to.be.equal(price.toString());
+
+    const sellerReceipt = await program.account.ticketReceipt.fetch(receiptOf(round, users[1].publicKey));
+    expect(Array.from(sellerReceipt.tickets)).to.deep.equal([kept]);
+    expect(sellerReceipt.listedTickets).to.be.equal(0);
+    expect(await ticketsOf(round, users[8].publicKey)).to.deep.equal([ticket]);
+    expect(await connection.getAccountInfo(listingOf(round, ticket))).to.be.null;
+  });
+
+  it("Charge the round creator's royalty on resales", async () => {
+    const resaleRound = await createRound();
+    await program.methods.updateAppStats(feePercent, PublicKey.default, 10, 0).accounts({
+      signer: resaleRound.creator.publicKey,
+      feeAccount: feeAccount.publicKey,
+      appStats: resaleRound.appStats
+    }).signers([resaleRound.creator]).rpc();
+    await buyTickets(resaleRound, 2, 1);
+    const [ticket] = await ticketsOf(resaleRound, users[2].publicKey);
+    const price = ticketPrice.muln(2);
+    await listTicket(resaleRound, 2, ticket, price);
+
+    // app stats of another operator carry their own royalty, none here
+    const other = await newOperator();
+    await expectError(buyListedTicket(resaleRound, 2, ticket, 3, {}, other.publicKey), "ConstraintRaw");
+
+    const royalty = price.muln(10).divn(100);
+    const sellerBefore = await tokenBalance(usersAtas[2].address);
+    const feeBefore = await tokenBalance(feeAccountAta.address);
+    await buyListedTicket(resaleRound, 2, ticket, 3);
+    const sellerAfter = await tokenBalance(usersAtas[2].address);
+    const feeAfter = await tokenBalance(feeAccountAta.address);
+    expect(feeAfter.sub(feeBefore).toString()).to.be.equal(royalty.toString());
+    expect(sellerAfter.sub(sellerBefore).toString()).to.be.equal(price.sub(royalty).toString());
+    expect(await ticketsOf(resaleRound, users[3].publicKey)).to.deep.equal([ticket]);
+  });
+
+  function mintTicket(round: Round, holder: number, ticket: number) {
+    const ticketMint = pda(Buffer.from("ticket-mint"), round.lottery.toBuffer(), u32le(ticket))[0];
+    return program.methods.mintTicket(ticket).accounts({
+      lottery: round.lottery,
+      receipt: receiptOf(round, users[holder].publicKey),
+      user: users[holder].publicKey,
+      ticketMint,
+      ticketToken: getAssociatedTokenAddressSync(ticketMint, users[holder].publicKey)
+    }).signers([users[holder]]).rpc();
+  }
+
+  it("Mint a ticket as a token", async () => {
+    const nftRound = await createRound({ nftTickets: true });
+    await buyTickets(nftRound, 2, 1);
+    const [ticket] = await ticketsOf(nftRound, users[2].publicKey);
+    await mintTicket(nftRound, 2, ticket);
+
+    // the ticket leaves the receipt but still counts against the holder's cap
+    const receipt = await program.account.ticketReceipt.fetch(receiptOf(nftRound, users[2].publicKey));
+    expect(receipt.tickets.length).to.be.equal(0);
+    expect(receipt.mintedTickets).to.be.equal(1);
+
+    // a single token whose supply can't grow
+    const ticketMint = pda(Buffer.from("ticket-mint"), nftRound.lottery.toBuffer(), u32le(ticket))[0];
+    const mintInfo = await getMint(connection, ticketMint);
+    expect(mintInfo.supply.toString()).to.be.equal("1");
+    expect(mintInfo.decimals).to.be.equal(0);
+    expect(mintInfo.mintAuthority).to.be.null;
+    const ticketToken = getAssociatedTokenAddressSync(ticketMint, users[2].publicKey);
+    expect((await tokenBalance(ticketToken)).toString()).to.be.equal("1");
+  });
+
+  it("Should not mint tickets of rounds without token tickets", async () => {
+    const [ticket] = await ticketsOf(round, users[2].publicKey);
+    await expectError(mintTicket(round, 2, ticket), "NftTicketsDisabled");
+    await expectError(createRound({ nftTickets: true, gateMint: mint, gateMinBalance: new BN(1) }), "RestrictedNftTickets");
+  });
+
+  it("Should not cap rounds with token tickets", async () => {
+    // ticket tokens move with plain SPL transfers, which would skip the receiver's cap
+    await expectError(createRound({ nftTickets: true, maxTicketsPerBuyer: 3 }), "RestrictedNftTickets");
+    const nftRound = await createRound({ nftTickets: true });
+    await expectError(program.methods.updateMaxTicketsPerBuyer(3).accounts({
+      lottery: nftRound.lottery,
+      creator: nftRound.creator.publicKey
+    }).signers([nftRound.creator]).rpc(), "RestrictedNftTickets");
+  });
+
+  it("Should hold every wallet to max_tickets_per_buyer", async () => {
+    const cappedRound = await createRound({ maxTicketsPerBuyer: 3 });
+    await buyTickets(cappedRound, 3, 2);
+    await expectError(buyTickets(cappedRound, 3, 2), "MaxTicketsPerBuyer");
+    await buyTickets(cappedRound, 3, 1);
+
+    // a lowered cap keeps the tickets already held but stops new ones, bought or received
+    await program.methods.updateMaxTicketsPerBuyer(2).accounts({
+      lottery: cappedRound.lottery,
+      creator: cappedRound.creator.publicKey
+    }).signers([cappedRound.creator]).rpc();
+    const lotteryInfo = await program.account.lottery.fetch(cappedRound.lottery);
+    expect(lotteryInfo.maxTicketsPerBuyer).to.be.equal(2);
+
+    await buyTickets(cappedRound, 4, 2);
+    await expectError(buyTickets(cappedRound, 4, 1), "MaxTicketsPerBuyer");
+    const [ticket] = await ticketsOf(cappedRound, users[3].publicKey);
+    await expectError(transferTicket(cappedRound, 3, ticket, users[4].publicKey), "MaxTicketsPerBuyer");
+    expect((await ticketsOf(cappedRound, users[3].publicKey)).length).to.be.equal(3);
+  });
+
+  const sha256 = (...parts: Buffer[]) => createHash("sha256").update(Buffer.concat(parts)).digest();
+
+  // see merkle_tools: leaves are sha256(wallet || allowance), parents hash their children sorted
+  function allowlistLeaf(wallet: PublicKey, allowance: number): Buffer {
+    return sha256(wallet.toBuffer(), u32le(allowance));
+  }
+
+  function hashPair(a: Buffer, b: Buffer): Buffer {
+    return Buffer.compare(a, b) <= 0 ? sha256(a, b) : sha256(b, a);
+  }
+
+  // tree levels from the leaves up, an odd node out moves up unchanged
+  function merkleTree(leaves: Buffer[]): Buffer[][] {
+    const levels = [leaves];
+    while (levels[levels.length - 1].length > 1) {
+      const level = levels[levels.length - 1];
+      const next: Buffer[] = [];
+      for (let i = 0; i < level.length; i += 2) {
+        next.push(i + 1 < level.length ? hashPair(level[i], level[i + 1]) : level[i]);
+      }
+      levels.push(next);
+    }
+    return levels;
+  }
+
+  function merkleProof(levels: Buffer[][], index: number): number[][] {
+    const proof: number[][] = [];
+    for (const level of levels.slice(0, -1)) {
+      const sibling = index ^ 1;
+      if (sibling < level.length) {
+        proof.push(Array.from(level[sibling]));
+      }
+      index = Math.floor(index / 2);
+    }
+    return proof;
+  }
+
+  it("Should only let allowlisted wallets hold tickets of a private round", async () => {
+    // users 5, 6 and 7 with allowances 2, none and 1
+    const allowances = [2, 0, 1];
+    const levels = merkleTree(allowances.map((allowance, i) => allowlistLeaf(users[5 + i].publicKey, allowance)));
+    const entry = (user: number): Entry => ({ proof: merkleProof(levels, user - 5), allowance: allowances[user - 5] });
+    const privateRound = await createRound({ allowlistRoot: Array.from(levels[levels.length - 1][0]) });
+
+    await buyTickets(privateRound, 5, 2, entry(5));
+    await buyTickets(privateRound, 6, 3, entry(6));
+    expect((await ticketsOf(privateRound, users[5].publicKey)).length).to.be.equal(2);
+    expect((await ticketsOf(privateRound, users[6].publicKey)).length).to.be.equal(3);
+
+    // over the allowance, a made-up allowance, a wallet that is not listed
+    await expectError(buyTickets(privateRound, 5, 1, entry(5)), "MaxTicketsPerBuyer");
+    await expectError(buyTickets(privateRound, 5, 1, { proof: entry(5).proof, allowance: 3 }), "NotAllowlisted");
+    await expectError(buyTickets(privateRound, 8, 1), "NotAllowlisted");
+    await expectError(buyTickets(privateRound, 8, 1, entry(7)), "NotAllowlisted");
+
+    // tickets only move to allowlisted wallets
+    const [ticket] = await ticketsOf(privateRound, users[5].publicKey);
+    await expectError(transferTicket(privateRound, 5, ticket, users[8].publicKey), "NotAllowlisted");
+    await transferTicket(privateRound, 5, ticket, users[7].publicKey, entry(7));
+    expect(await ticketsOf(privateRound, users[7].publicKey)).to.deep.equal([ticket]);
+
+    const [listed] = await ticketsOf(privateRound, users[6].publicKey);
+    await listTicket(privateRound, 6, listed, ticketPrice);
+    await expectError(buyListedTicket(privateRound, 6, listed, 8), "NotAllowlisted");
+    await expectError(buyListedTicket(privateRound, 6, listed, 7, entry(7)), "MaxTicketsPerBuyer");
+    await buyListedTicket(privateRound, 6, listed, 5, entry(5));
+    const held = await ticketsOf(privateRound, users[5].publicKey);
+    expect(held.length).to.be.equal(2);
+    expect(held).to.include(listed);
+  });
+
+  it("Should only let holders of the gate token hold tickets of a gated round", async () => {
+    const gateMint = await createMint(connection, owner.payer, owner.publicKey, null, 0);
+    const gateTokens: PublicKey[] = [];
+    for (const [user, balance] of [[0, 5], [1, 4], [2, 5]]) {
+      const gateToken = await getOrCreateAssociatedTokenAccount(connection, owner.payer, gateMint, users[user].publicKey);
+      await mintTo(connection, owner.payer, gateMint, gateToken.address, owner.payer, balance);
+      gateTokens[user] = gateToken.address;
+    }
+    const gatedRound = await createRound({ gateMint, gateMinBalance: new BN(5) });
+
+    await buyTickets(gatedRound, 0, 2, { gateToken: gateTokens[0] });
+    expect((await ticketsOf(gatedRound, users[0].publicKey)).length).to.be.equal(2);
+
+    // too small a balance, someone else's gate token, no gate token
+    await expectError(buyTickets(gatedRound, 1, 1, { gateToken: gateTokens[1] }), "GateBalanceTooLow");
+    await expectError(buyTickets(gatedRound, 1, 1, { gateToken: gateTokens[0] }), "GateBalanceTooLow");
+    await expectError(buyTickets(gatedRound, 1, 1), "GateBalanceTooLow");
+    // a token account of another mint
+    await expectError(buyTickets(gatedRound, 1, 1, { gateToken: usersAtas[1].address }), "ConstraintRaw");
+
+    // the receiving wallet must hold the gate token as well
+    const [ticket] = await ticketsOf(gatedRound, users[0].publicKey);
+    await expectError(transferTicket(gatedRound, 0, ticket, users[1].publicKey, { gateToken: gateTokens[1] }), "GateBalanceTooLow");
+    await transferTicket(gatedRound, 0, ticket, users[2].publicKey, { gateToken: gateTokens[2] });
+    expect(await ticketsOf(gatedRound, users[2].publicKey)).to.deep.equal([ticket]);
+  });
+
+  it("Pay referrers their share of the fee", async () => {
+    const referralRound = await createRound();
+    await program.methods.updateAppStats(feePercent, PublicKey.default, 0, 50).accounts({
+      signer: referralRound.creator.publicKey,
+      feeAccount: feeAccount.publicKey,
+      appStats: referralRound.appStats
+    }).signers([referralRound.creator]).rpc();
+
+    const [referrerStats] = pda(Buffer.from("referrer"), users[9].publicKey.toBuffer(), mint.toBuffer());
+    const [referralVault] = pda(Buffer.from("referral-vault"), referrerStats.toBuffer());
+    await program.methods.createReferrerStats().accounts({
+      signer: users[9].publicKey,
+      referrerStats,
+      referralVault,
+      mint
+    }).signers([users[9]]).rpc();
+
+    const referredSale = (buyer: number) => program.methods.buyTickets(new BN(10), null, Buffer.from([]), [], 0).accounts({
+      sale: { ...sale(referralRound, buyer), referrerStats, referralVault },
+      receipt: receiptOf(referralRound, users[buyer].publicKey)
+    }).signers([users[buyer]]).rpc();
+
+    // half of the fee goes to the referrer's vault, the rest to the fee account
+    const fee = ticketPrice.muln(10).muln(feePercent).divn(100);
+    const feeBefore = await tokenBalance(feeAccountAta.address);
+    await referredSale(3);
+    const feeAfter = await tokenBalance(feeAccountAta.address);
+    expect(feeAfter.sub(feeBefore).toString()).to.be.equal(fee.sub(fee.divn(2)).toString());
+    expect((await tokenBalance(referralVault)).toString()).to.be.equal(fee.divn(2).toString());
+
+    const stats = await program.account.referrerStats.fetch(referrerStats);
+    expect(stats.pendingRewards.toString()).to.be.equal(fee.divn(2).toString());
+    expect(stats.referredTickets.toString()).to.be.equal("10");
+    expect(stats.referredVolume.toString()).to.be.equal(ticketPrice.muln(10).toString());
+
+    // nobody can refer their own tickets
+    await expectError(referredSale(9), "InvalidReferrer");
+
+    // nor route the whole fee to a referrer through app stats of their own
+    const other = await newOperator();
+    const [otherAppStats] = appStatsOf(other.publicKey);
+    await program.methods.updateAppStats(feePercent, PublicKey.default, 0, 100).accounts({
+      signer: other.publicKey,
+      feeAccount: feeAccount.publicKey,
+      appStats: otherAppStats
+    }).signers([other]).rpc();
+    await expectError(program.methods.buyTickets(new BN(1), null, Buffer.from([]), [], 0).accounts({
+      sale: { ...sale(referralRound, 4), referrerStats, referralVault, appStats: otherAppStats, owner: other.publicKey },
+      receipt: receiptOf(referralRound, users[4].publicKey)
+    }).signers([users[4]]).rpc(), "ConstraintRaw");
+
+    const referrerBefore = await tokenBalance(usersAtas[9].address);
+    await program.methods.claimReferralRewards().accounts({
+      referrerStats,
+      referralVault,
+      referrerToken: usersAtas[9].address,
+      referrer: users[9].publicKey
+    }).signers([users[9]]).rpc();
+    const referrerAfter = await tokenBalance(usersAtas[9].address);
+    expect(referrerAfter.sub(referrerBefore).toString()).to.be.equal(fee.divn(2).toString());
+    expect((await program.account.referrerStats.fetch(referrerStats)).pendingRewards.toString()).to.be.equal("0");
+  });
+
+  // the shared round sells tickets for a day, short rounds below run the draw and the raffle claims,
+  // the pick-numbers tier payouts and the refunds are covered by programs/lottery/tests/payouts.rs
+  it("Should not reveal the winners before the round ends", async () => {
+    const [drawRecord] = pda(Buffer.from("draw-record"), round.lottery.toBuffer());
+    await expectError(program.methods.revealWinners(Array.from(secret)).accounts({
+      lottery: round.lottery,
+      ticketPool: round.ticketPool,
+      slotHashes: anchor.web3.SYSVAR_SLOT_HASHES_PUBKEY,
+      payer: owner.publicKey,
+      drawRecord
+    }).rpc(), "RoundNotEnded");
+
+    const lotteryInfo = await program.account.lottery.fetch(round.lottery);
+    expect(lotteryInfo.status).to.deep.equal({ running: {} });
+    expect(lotteryInfo.winningTicketCount).to.be.equal(0);
+    expect(await connection.getAccountInfo(drawRecord)).to.be.null;
+  });
+
+  it("Should not save the target slot hash before the target slot", async () => {
+    await expectError(program.methods.saveSlotHash().accounts({
+      lottery: round.lottery,
+      slotHashes: anchor.web3.SYSVAR_SLOT_HASHES_PUBKEY
+    }).rpc(), "TargetSlotNotReached");
+
+    const lotteryInfo = await program.account.lottery.fetch(round.lottery);
+    expect(lotteryInfo.savedSlotHash).to.deep.equal(Array(32).fill(0));
+  });
+
+  it("Should draw an oracle round and pay its winners", async () => {
+    // an operator drawing from the mock oracle
+    const operator = await newOperator();
+    await program.methods.updateAppStats(feePercent, oracleProgram.programId, 0, 0).accounts({
+      signer: operator.publicKey,
+      feeAccount: feeAccount.publicKey,
+      appStats: appStatsOf(operator.publicKey)[0]
+    }).signers([operator]).rpc();
+    await expectError(createRound({ creator: operator, roundDuration: 0 }), "InvalidRoundDuration");
+
+    const oracleRound = await createRound({ creator: operator, ticketAmount: 20, roundDuration: 10 });
+    for (let buyer = 0; buyer < 5; buyer++) {
+      await buyTickets(oracleRound, buyer, 2);
+    }
+    await expectError(claimPrize(oracleRound, 0), "RoundNotEnded");
+    await waitPast((await program.account.lottery.fetch(oracleRound.lottery)).end.toNumber());
+
+    // the oracle opens a request for the round, the operator binds it and the oracle fulfills it
+    const [result] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("randomness"), oracleRound.lottery.toBuffer()],
+      oracleProgram.programId
+    );
+    await oracleProgram.methods.requestRandomness().accounts({
+      requester: oracleRound.lottery,
+      result
+    }).rpc();
+    await program.methods.requestRandomness().accounts({
+      lottery: oracleRound.lottery,
+      creator: operator.publicKey,
+      randomnessAccount: result
+    }).signers([operator]).rpc();
+
+    const [drawRecord] = pda(Buffer.from("draw-record"), oracleRound.lottery.toBuffer());
+    const consumeRandomness = () => program.methods.consumeRandomness().accounts({
+      lottery: oracleRound.lottery,
+      ticketPool: oracleRound.ticketPool,
+      randomnessAccount: result,
+      payer: owner.publicKey,
+      drawRecord
+    }).rpc();
+    await expectError(consumeRandomness(), "RandomnessNotFulfilled");
+    await oracleProgram.methods.fulfillRandomness(Array.from(randomBytes(32))).accounts({
+      result
+    }).rpc();
+    await consumeRandomness();
+    await finishDraw(oracleRound, drawRecord);
+
+    const lotteryInfo = await program.account.lottery.fetch(oracleRound.lottery);
+    expect(lotteryInfo.status).to.deep.equal({ ended: {} });
+    expect(lotteryInfo.winningTicketCount).to.be.equal(5);
+    expect((await program.account.drawRecord.fetch(drawRecord)).status).to.deep.equal({ ended: {} });
+
+    // every winning ticket gets the same share of the collected amount
+    const prize = lotteryInfo.collected.divn(lotteryInfo.winningTicketCount);
+    let paidTickets = 0;
+    for (let holder = 0; holder < 5; holder++) {
+      const tickets = await ticketsOf(oracleRound, users[holder].publicKey);
+      const won = (await Promise.all(tickets.map(ticket => isWinner(oracleRound, ticket)))).filter(Boolean).length;
+      if (won == 0) {
+        await expectError(claimPrize(oracleRound, holder), "InvalidWinner");
+        continue;
+      }
+      const before = await tokenBalance(usersAtas[holder].address);
+      await claimPrize(oracleRound, holder);
+      const after = await tokenBalance(usersAtas[holder].address);
+      expect(after.sub(before).toString()).to.be.equal(prize.muln(won).toString());
+      await expectError(claimPrize(oracleRound, holder), "AlreadyClaimd");
+      paidTickets += won;
+    }
+    expect(paidTickets).to.be.equal(5);
+  });
+
+  it("Should reveal the secret from the saved slot hash", async () => {
+    const revealRound = await createRound({ ticketAmount: 20, roundDuration: 10 });
+    for (let buyer = 0; buyer < 5; buyer++) {
+      await buyTickets(revealRound, buyer, 2);
+    }
+
+    // anyone saves the hash once the target slot passed, the secret is revealed from it later
+    const saveSlotHash = () => program.methods.saveSlotHash().accounts({
+      lottery: revealRound.lottery,
+      slotHashes: anchor.web3.SYSVAR_SLOT_HASHES_PUBKEY
+    }).rpc();
+    const { targetSlot } = await program.account.lottery.fetch(revealRound.lottery);
+    while (await connection.getSlot() <= targetSlot.toNumber()) {
+      await sleep(1000);
+    }
+    await saveSlotHash();
+    await expectError(saveSlotHash(), "SlotHashSaved");
+    const { savedSlotHash } = await program.account.lottery.fetch(revealRound.lottery);
+    expect(savedSlotHash).to.not.deep.equal(Array(32).fill(0));
+
+    const [drawRecord] = pda(Buffer.from("draw-record"), revealRound.lottery.toBuffer());
+    await program.methods.revealWinners(Array.from(secret)).accounts({
+      lottery: revealRound.lottery,
+      ticketPool: revealRound.ticketPool,
+      slotHashes: anchor.web3.SYSVAR_SLOT_HASHES_PUBKEY,
+      payer: owner.publicKey,
+      drawRecord
+    }).rpc();
+    await finishDraw(revealRound, drawRecord);
+
+    const record = await program.account.drawRecord.fetch(drawRecord);
+    expect(Array.from(record.slotHash)).to.deep.equal(Array.from(savedSlotHash));
+    expect(Array.from(record.randomness)).to.deep.equal(Array.from(secret));
+    expect((await program.account.lottery.fetch(revealRound.lottery)).status).to.deep.equal({ ended: {} });
+  });
+
+  it("Should list every round of an operator", async () => {
+    // the shared round is the owner's first, two more follow it
+    const [ownerAppStats] = appStatsOf(owner.publicKey);
+    const before = await program.account.appStats.fetch(ownerAppStats);
+    const rounds = [await createRound({ creator: owner.payer }), await createRound({ creator: owner.payer })];
+
+    const appStats = await program.account.appStats.fetch(ownerAppStats);
+    expect(appStats.currentRound.toNumber()).to.be.equal(before.currentRound.toNumber() + 2);
+    expect(appStats.currentRoundKey.toBase58()).to.be.equal(rounds[1].lottery.toBase58());
+    expect(appStats.currentRoundList.map(key => key.toBase58())).to.include.members(
+      [round.lottery, ...rounds.map(listed => listed.lottery)].map(key => key.toBase58())
+    );
+    expect(appStats.currentRoundList.length).to.be.at.least(3);
+  });
 
   it("Should get lotteryInfo", async () => {
 
@@ -237,6 +1173,32 @@ describe("Lottery", () => {
     //const lotteryInfo = await program.account.lottery.fetch(lotteryAccount.publicKey);
   });
 
+  it("Should request and fulfill mock oracle randomness", async () => {
+    const requester = anchor.web3.Keypair.generate().publicKey;
+    const [result] = PublicKey.findProgramAddressSync(
+      [anchor.utils.bytes.utf8.encode("randomness"), requester.toBuffer()],
+      oracleProgram.programId
+    );
+
+    await oracleProgram.methods.requestRandomness().accounts({
+      requester,
+      result
+    }).rpc();
+
+    let resultInfo = await oracleProgram.account.randomnessResult.fetch(result);
+    expect(resultInfo.requester.toBase58()).to.be.equal(requester.toBase58());
+    expect(resultInfo.fulfilled).to.be.equal(false);
+
+    const randomness = Array.from(randomBytes(32));
+    await oracleProgram.methods.fulfillRandomness(randomness).accounts({
+      result
+    }).rpc();
+
+    resultInfo = await oracleProgram.account.randomnessResult.fetch(result);
+    expect(resultInfo.fulfilled).to.be.equal(true);
+    expect(Array.from(resultInfo.randomness)).to.deep.equal(randomness);
+  });
+
   // const lotteryInfo = await program.account.lottery.fetch(lotteryAccount.publicKey);
   // const ticketAmount = lotteryInfo.ticketAmount
   // const leftTickets = lotteryInfo.leftTickets.length
@@ -249,96 +1211,6 @@ describe("Lottery", () => {
   //console.log(lotteryInfo);
 })
 
-// it("Buy tickets", async () => {
-
-//   const [prize, prize_bump] = PublicKey.findProgramAddressSync(
-//     [anchor.utils.bytes.utf8.encode("prize"), lotteryAccount.publicKey.toBuffer()],
-//     program.programId
-//   );
-
-//   const [proceeds, proceeds_bump] = PublicKey.findProgramAddressSync(
-//     [
-//       anchor.utils.bytes.utf8.encode('proceeds'),
-//       lotteryAccount.publicKey.toBuffer(),
-//     ],
-//     program.programId
-//   );
-
-//   const [appStats, bump] = PublicKey.findProgramAddressSync(
-//     [
-//       anchor.utils.bytes.utf8.encode('app-stats'),
-//       owner.publicKey.toBuffer()
-//     ],
-//     program.programId
-//   );
-
-
-//   for (let i = 0; i < users.length; i++) {
-
-
-//     const tx1 = await program.methods.buyTickets(
-//       new BN(2)
-//     ).accounts({
-//       prize,
-//       creatorToken: usersAtas[i].address,
-//       lottery: lotteryAccount.publicKey,
-//       signer: users[i].publicKey,
-//       proceeds,
-//       appStats,
-//       owner: owner.publicKey,
-//       feeAccount:feeAccount.publicKey
-//     }).signers([users[i]]).rpc();
-
-//     console.log("user1 " + users[i].publicKey + " bought a ticket txn hash is ", tx1);
-//   }
-
-//   // balance = await connection.getBalance(user1.publicKey);
-//   // console.log("balance of user1 is ", balance / LAMPORTS_PER_SOL);
-
-//   const lotteryInfo = await program.account.lottery.fetch(lotteryAccount.publicKey);
-//   const ticketAmount = lotteryInfo.ticketAmount
-//   const leftTickets = lotteryInfo.leftTickets.length
-//   const ticketPrice = lotteryInfo.ticketPrice
-//   const buyers = lotteryInfo.buyers
-
-//   for (let i = 0; i < buyers.length; i++) {
-//     const buyer = buyers[i];
-//     console.log({
-//       participant: buyer.participant.toBase58(),
-//       tickets: Array.from(buyer.tickets)
-//     })
-//   }
-
-//   console.log({
-//     //lotteryInfo,
-//     //buyers,
-//     leftTickets,
-//     ticketAmount,
-//     ticketPrice: Number(ticketPrice.toString()) / Math.pow(10, 9)
-//   })
-
-//   const feeAccountTokenBalance = await connection.getTokenAccountBalance(feeAccountAta.address);
-//   console.log("feeAccount token balance is ", feeAccountTokenBalance.value.uiAmount);
-// });
-
-// it("Reveal winners", async () => {
-//   await sleep(2000)
-//   const tx = await program.methods.revealWinners().accounts({
-//     lottery: lotteryAccount.publicKey,
-//     clock: anchor.web3.SYSVAR_CLOCK_PUBKEY
-//   }).rpc().catch(e => console.log(e));
-//   const raffleInfo = await program.account.lottery.fetch(lotteryAccount.publicKey);
-//   console.log(raffleInfo);
-//   console.log({
-//     collected: raffleInfo.collected.toString(),
-//   });
-//   //winners = raffleInfo.winners;
-//   //console.log("Winners are ", winners.map(winner => winner.toBase58()));
-//   //console.log(tx);
-//   //winner = users.find(user => user.publicKey.toBase58() === raffleInfo.winner.toBase58());
-//   //console.log('Winner is', raffleInfo.winner.toBase58());
-// });
-
 // it("Claim prize", async () => {
 
 //   // let winner token balance before claiming
@@ -361,13 +1233,25 @@ describe("Lottery", () => {
 //     program.programId
 //   );
 
+//   const [receipt] = PublicKey.findProgramAddressSync(
+//     [
+//       anchor.utils.bytes.utf8.encode('tickets'),
+//       lotteryAccount.publicKey.toBuffer(),
+//       winner.publicKey.toBuffer(),
+//     ],
+//     program.programId
+//   );
+
 //   const tx = await program.methods.claimPrize().accounts(
 //     {
 //       user: winner.publicKey,
 //       lottery: lotteryAccount.publicKey,
+//       ticketPool: ticketPool.publicKey,
+//       receipt,
 //       prize,
 //       mint,
-//       userToken: winnerToken.address
+//       userToken: winnerToken.address,
+//       owner: owner.publicKey
 //     }
 //   ).signers([winner]).rpc();
 
@@ -0,0 +1,231 @@
+use anchor_lang::Discriminator;
+use anchor_lang::solana_program::hash::hash;
+use lottery::{ DrawRecord, Lottery, LotteryMode, LotteryStatus, RandomnessSource, TicketPool, DRAW_ALGORITHM_VERSION };
+
+/*
+Checks of the lottery-verify tool, which re-runs a lottery draw offline and compares it
+with what is stored on chain.
+The draw itself is replayed with `Lottery::start_draw` and `draw_batch` from the program crate over
+a copy of the ticket pool, so the verifier and the program always run the same code.
+Raffle draws shuffle the sold slots in place, their order before the draw is recovered with
+`TicketPool::undo_draw` and must match the recorded sold tickets hash.
+*/
+
+/// What `verify` found, printed by the lottery-verify binary
+#[derive(Debug, Default)]
+pub struct Report {
+    // failed checks, empty when the round verifies
+    pub failures: Vec<String>,
+    pub sold_tickets: usize,
+    // where the draw randomness came from, and what has to be checked against a ledger or RPC node
+    pub randomness: String,
+    // false when the round was not drawn, the outcome below is then empty
+    pub drawn: bool,
+    pub winning_numbers: Vec<u8>,
+    pub tier_winners: Vec<u32>,
+    // recomputed winning tickets in draw order
+    pub winning_tickets: Vec<u32>,
+}
+
+impl Report {
+    pub fn verified(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+// zero-copy header and the ticket slots after it
+pub fn parse_ticket_pool(data: &[u8]) -> std::result::Result<(TicketPool, Vec<u8>), String> {
+    if data.len() < TicketPool::HEADER_SIZE || data[..8] != TicketPool::DISCRIMINATOR {
+        return Err("not a ticket pool account".to_string());
+    }
+    let ticket_pool: TicketPool = bytemuck::pod_read_unaligned(&data[8..TicketPool::HEADER_SIZE]);
+    if data.len() < TicketPool::space(ticket_pool.capacity, ticket_pool.pick_count as u8) {
+        return Err("ticket pool is truncated".to_string());
+    }
+    Ok((ticket_pool, data[TicketPool::HEADER_SIZE..].to_vec()))
+}
+
+pub fn verify(lottery: &Lottery, ticket_pool: &TicketPool, pool_tickets: &[u8], record: &DrawRecord) -> Report {
+    let mut report = Report::default();
+    let failures = &mut report.failures;
+
+    if record.algorithm_version != DRAW_ALGORITHM_VERSION {
+        failures.push(format!(
+            "draw algorithm version {} is not supported, this verifier implements {}",
+            record.algorithm_version, DRAW_ALGORITHM_VERSION
+        ));
+        return report;
+    }
+
+    // the ticket snapshot must be the one the draw ran over
+    if ticket_pool.lottery != record.lottery {
+        failures.push(format!(
+            "ticket pool belongs to lottery {}, the draw record to {}",
+            ticket_pool.lottery, record.lottery
+        ));
+    }
+    if ticket_pool.capacity != lottery.ticket_amount {
+        failures.push(format!(
+            "ticket pool holds {} tickets, lottery sells {}",
+            ticket_pool.capacity, lottery.ticket_amount
+        ));
+    }
+    let sold = ticket_pool.capacity - ticket_pool.remaining;
+    report.sold_tickets = sold as usize;
+    if record.sold_ticket_count != sold || lottery.tickets_sold != sold {
+        failures.push(format!(
+            "sold ticket count {} in draw record, {} in lottery, {} in ticket pool",
+            record.sold_ticket_count, lottery.tickets_sold, sold
+        ));
+    }
+    if ticket_pool.pick_count != lottery.pick_count as u32 {
+        failures.push(format!(
+            "ticket pool holds {} numbers per ticket, lottery {}",
+            ticket_pool.pick_count, lottery.pick_count
+        ));
+    }
+
+    // entropy sources
+    if record.randomness_source != lottery.randomness_source {
+        failures.push(format!(
+            "randomness source {:?} in draw record, {:?} in lottery",
+            record.randomness_source, lottery.randomness_source
+        ));
+    }
+    // past the reveal deadline the round turned unresolved, no randomness was taken
+    if record.drawn_at > lottery.reveal_deadline {
+        report.randomness = format!(
+            "none, the round missed its reveal deadline {} and turned unresolved at {}",
+            lottery.reveal_deadline, record.drawn_at
+        );
+        if record.status != LotteryStatus::Unresolved || record.randomness != [0u8; 32] || record.slot_hash != [0u8; 32] {
+            failures.push("draw record past the reveal deadline holds a draw".to_string());
+        }
+    } else {
+        match record.randomness_source {
+            RandomnessSource::CommitReveal => {
+                if record.secret_commitment != lottery.secret_commitment {
+                    failures.push("secret commitment differs from the lottery commitment".to_string());
+                }
+                if hash(&record.randomness).to_bytes() != record.secret_commitment {
+                    failures.push("revealed secret does not hash to the commitment".to_string());
+                }
+                // the slot hash itself has to be checked against a ledger or RPC node
+                report.randomness = format!(
+                    "commit-reveal, slot hash of the first slot at or after {}: {:?}",
+                    record.target_slot, record.slot_hash
+                );
+                if record.target_slot != lottery.target_slot {
+                    failures.push("target slot differs from the lottery".to_string());
+                }
+            },
+            RandomnessSource::Oracle => {
+                report.randomness = format!("oracle {} account {}", record.oracle_program, record.randomness_account);
+                if record.oracle_program != lottery.oracle_program || record.randomness_account != lottery.randomness_account {
+                    failures.push("oracle accounts differ from the lottery".to_string());
+                }
+            },
+            RandomnessSource::SlotHash => {
+                // the slot hash itself has to be checked against a ledger or RPC node
+                report.randomness = format!("hash of the first slot at or after {}: {:?}", record.target_slot, record.slot_hash);
+                if record.target_slot != lottery.target_slot {
+                    failures.push("target slot differs from the lottery".to_string());
+                }
+                if record.slot_hash != record.randomness {
+                    failures.push("slot hash differs from the draw randomness".to_string());
+                }
+            },
+        }
+    }
+
+    // buyers' client seeds
+    if record.entropy_accumulator != lottery.entropy_accumulator {
+        failures.push("entropy accumulator differs from the lottery".to_string());
+    }
+
+    if record.status != lottery.status {
+        failures.push(format!("status {:?} in draw record, {:?} in lottery", record.status, lottery.status));
+    }
+    // a running draw is checked once continue_draw ended it
+    if record.status == LotteryStatus::Drawing {
+        return report;
+    }
+
+    // the sold slots as they were hashed, before a raffle draw shuffled them
+    let mut snapshot = pool_tickets.to_vec();
+    if record.status == LotteryStatus::Ended && lottery.mode == LotteryMode::Raffle {
+        ticket_pool.undo_draw(&mut snapshot, &record.stream_seed(), sold / 2);
+    }
+    // rounds that were not drawn only hash their tickets when they sold too few
+    if record.hashed_ticket_count == sold && record.sold_tickets_hash != ticket_pool.sold_snapshot_hash(&snapshot) {
+        failures.push("sold tickets hash does not match the ticket pool".to_string());
+    }
+    if record.status != LotteryStatus::Ended {
+        if record.drawn_ticket_count != 0 || lottery.winning_ticket_count != 0 {
+            failures.push("winners recorded for a round that was not drawn".to_string());
+        }
+        return report;
+    }
+    report.drawn = true;
+    if record.hashed_ticket_count != sold {
+        failures.push(format!("draw record hashed {} of {} sold tickets", record.hashed_ticket_count, sold));
+    }
+
+    // the program's draw over the snapshot without winners, seeded from the record
+    let mut replay = lottery.clone();
+    replay.status = LotteryStatus::Running;
+    replay.tickets_sold = sold;
+    let mut replay_record = record.replay_inputs();
+    let mut replay_tickets = snapshot;
+    for ticket in 1..=ticket_pool.capacity {
+        ticket_pool.set_winner(&mut replay_tickets, ticket, false);
+    }
+    replay.start_draw(ticket_pool, &mut replay_tickets, &mut replay_record);
+    while replay.status == LotteryStatus::Drawing {
+        replay.draw_batch(ticket_pool, &mut replay_tickets, &mut replay_record);
+    }
+    // raffle winners sit in the first sold slots in draw order, pick-numbers winners are in slot order
+    let sold_slots = ticket_pool.remaining as usize..ticket_pool.capacity as usize;
+    report.winning_tickets = match lottery.mode {
+        LotteryMode::Raffle => sold_slots.take(replay.winning_ticket_count as usize)
+            .map(|slot| TicketPool::ticket_at(&replay_tickets, slot))
+            .collect(),
+        LotteryMode::PickNumbers => sold_slots
+            .map(|slot| TicketPool::ticket_at(&replay_tickets, slot))
+            .filter(|&ticket| ticket_pool.is_winner(&replay_tickets, ticket))
+            .collect(),
+    };
+    report.winning_numbers = replay.winning_numbers.clone();
+    report.tier_winners = replay.tier_winners.clone();
+    let drawn = &report.winning_tickets;
+
+    if replay.winning_numbers != record.drawn_numbers {
+        failures.push(format!("draw record numbers {:?}", record.drawn_numbers));
+    }
+    if replay.winning_numbers != lottery.winning_numbers {
+        failures.push("lottery winning numbers differ from the recomputed draw".to_string());
+    }
+    // the claim payouts depend on how many tickets each tier pays
+    if replay.tier_winners != lottery.tier_winners {
+        failures.push(format!("lottery tier winners {:?}", lottery.tier_winners));
+    }
+    if drawn.len() != record.drawn_ticket_count as usize || drawn.len() != lottery.winning_ticket_count as usize {
+        failures.push(format!(
+            "drawn ticket count {} in draw record, {} in lottery",
+            record.drawn_ticket_count, lottery.winning_ticket_count
+        ));
+    }
+    if replay_record.drawn_tickets_hash != record.drawn_tickets_hash {
+        failures.push("draw record tickets hash does not match the recomputed draw".to_string());
+    }
+
+    // claims pay the tickets marked in the pool's winner bitmap
+    let misplaced: Vec<u32> = (1..=ticket_pool.capacity)
+        .filter(|&ticket| ticket_pool.is_winner(pool_tickets, ticket) != ticket_pool.is_winner(&replay_tickets, ticket))
+        .collect();
+    if !misplaced.is_empty() {
+        failures.push(format!("ticket pool marks winning tickets differently for tickets {:?}", misplaced));
+    }
+
+    report
+}
@@ -0,0 +1,77 @@
+use anchor_lang::prelude::*;
+use lottery::{ DrawRecord, Lottery, LotteryStatus, TicketPool };
+use lottery_verify::{ parse_ticket_pool, verify, Report };
+use std::{ env, fs, process };
+
+/*
+Re-runs a lottery draw offline and checks it against what is stored on chain.
+
+usage: lottery-verify <lottery-account-file> <ticket-pool-account-file> <draw-record-account-file>
+
+All files hold the raw account data, e.g. as written by
+`solana account <address> --output-file <file>`.
+The checks themselves live in the library, see `verify`.
+*/
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 4 {
+        eprintln!("usage: {} <lottery-account-file> <ticket-pool-account-file> <draw-record-account-file>", args[0]);
+        process::exit(2);
+    }
+
+    let lottery: Lottery = load_account(&args[1]);
+    let (ticket_pool, pool_tickets) = load_ticket_pool(&args[2]);
+    let record: DrawRecord = load_account(&args[3]);
+
+    let report = verify(&lottery, &ticket_pool, &pool_tickets, &record);
+    print_report(&report, &record);
+    if !report.verified() {
+        process::exit(1);
+    }
+}
+
+fn print_report(report: &Report, record: &DrawRecord) {
+    println!("sold tickets: {}", report.sold_tickets);
+    if !report.randomness.is_empty() {
+        println!("randomness source: {}", report.randomness);
+    }
+    if report.drawn {
+        if !report.winning_numbers.is_empty() {
+            println!("recomputed winning numbers: {:?}", report.winning_numbers);
+            println!("recomputed tier winners: {:?}", report.tier_winners);
+        }
+        println!("recomputed winning tickets: {}", report.winning_tickets.len());
+    } else if record.status != LotteryStatus::Ended {
+        println!("round was not drawn, status {:?}", record.status);
+    }
+
+    if report.verified() {
+        println!("OK: on-chain winners match the recomputed draw");
+    }
+    for failure in report.failures.iter() {
+        println!("MISMATCH: {}", failure);
+    }
+}
+
+fn read_file(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| {
+        eprintln!("cannot read {}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn load_account<T: AccountDeserialize>(path: &str) -> T {
+    let data = read_file(path);
+    T::try_deserialize(&mut data.as_slice()).unwrap_or_else(|e| {
+        eprintln!("cannot deserialize {}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn load_ticket_pool(path: &str) -> (TicketPool, Vec<u8>) {
+    parse_ticket_pool(&read_file(path)).unwrap_or_else(|e| {
+        eprintln!("cannot deserialize {}: {}", path, e);
+        process::exit(2);
+    })
+}
@@ -0,0 +1,341 @@
+// Rounds are built the way the program stores them, then checked with `verify` as they are and
+// after tampering with one of the stored values.
+//
+//     cargo test -p lottery-verify
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash::hash;
+use anchor_lang::Discriminator;
+use lottery::randomness_tools::RandomnessStream;
+use lottery::{ DrawRecord, Lottery, LotteryMode, LotteryStatus, PrizeTier, RandomnessSource, TicketPool, DRAW_ALGORITHM_VERSION, DRAW_BATCH };
+use lottery_verify::{ parse_ticket_pool, verify, Report };
+
+const CAPACITY: u32 = 40;
+const SOLD: u32 = 24;
+const SECRET: [u8; 32] = [3u8; 32];
+const SLOT_HASH: [u8; 32] = [9u8; 32];
+
+struct Round {
+    lottery: Lottery,
+    ticket_pool: TicketPool,
+    pool_tickets: Vec<u8>,
+    record: DrawRecord,
+}
+
+impl Round {
+    fn verify(&self) -> Report {
+        verify(&self.lottery, &self.ticket_pool, &self.pool_tickets, &self.record)
+    }
+
+    fn set_winner(&mut self, ticket: u32, winner: bool) {
+        self.ticket_pool.set_winner(&mut self.pool_tickets, ticket, winner);
+    }
+
+    fn picks_offset(&self, ticket: u32) -> usize {
+        4 * self.ticket_pool.capacity as usize + (ticket as usize - 1) * self.ticket_pool.pick_count as usize
+    }
+}
+
+// SOLD of CAPACITY tickets sold and the draw inputs recorded, not drawn yet
+fn round(randomness_source: RandomnessSource, pick_count: u8) -> Round {
+    round_of(randomness_source, pick_count, CAPACITY, SOLD)
+}
+
+fn round_of(randomness_source: RandomnessSource, pick_count: u8, capacity: u32, sold: u32) -> Round {
+    let mut ticket_pool = TicketPool {
+        lottery: Pubkey::new_unique(),
+        capacity,
+        remaining: capacity,
+        pick_count: pick_count as u32,
+    };
+    let mut pool_tickets = vec![0u8; TicketPool::space(capacity, pick_count) - TicketPool::HEADER_SIZE];
+    let mut stream = RandomnessStream::from_seed_and_nonce(7, 0);
+    for _ in 0..sold {
+        ticket_pool.take_random_ticket(&mut pool_tickets, &mut stream);
+    }
+
+    let lottery = Lottery {
+        ticket_amount: capacity,
+        tickets_sold: sold,
+        pick_count,
+        target_slot: 1_000,
+        entropy_accumulator: [5u8; 32],
+        randomness_source: randomness_source.clone(),
+        secret_commitment: hash(&SECRET).to_bytes(),
+        status: LotteryStatus::Running,
+        ..Lottery::default()
+    };
+
+    let mut record = DrawRecord::default();
+    record.lottery = ticket_pool.lottery;
+    record.algorithm_version = DRAW_ALGORITHM_VERSION;
+    record.randomness_source = randomness_source.clone();
+    record.secret_commitment = lottery.secret_commitment;
+    record.target_slot = lottery.target_slot;
+    record.entropy_accumulator = lottery.entropy_accumulator;
+    record.sold_ticket_count = sold;
+    (record.randomness, record.slot_hash) = match randomness_source {
+        RandomnessSource::CommitReveal => (SECRET, SLOT_HASH),
+        RandomnessSource::Oracle => ([4u8; 32], [0u8; 32]),
+        RandomnessSource::SlotHash => (SLOT_HASH, SLOT_HASH),
+    };
+
+    Round { lottery, ticket_pool, pool_tickets, record }
+}
+
+// runs the program's draw batch by batch, like reveal_winners and continue_draw
+fn draw(mut round: Round) -> Round {
+    round.lottery.start_draw(&round.ticket_pool, &mut round.pool_tickets, &mut round.record);
+    while round.lottery.status == LotteryStatus::Drawing {
+        round.lottery.draw_batch(&round.ticket_pool, &mut round.pool_tickets, &mut round.record);
+    }
+    round
+}
+
+fn raffle(randomness_source: RandomnessSource) -> Round {
+    draw(round(randomness_source, 0))
+}
+
+// three numbers out of 1..=8 on every sold ticket, paid from two matches up
+fn pick_numbers() -> Round {
+    draw(pick_numbers_round(round(RandomnessSource::SlotHash, 3)))
+}
+
+fn pick_numbers_round(mut round: Round) -> Round {
+    round.lottery.mode = LotteryMode::PickNumbers;
+    round.lottery.pick_range = 8;
+    round.lottery.prize_tiers = vec![PrizeTier { matches: 3, percent: 50 }, PrizeTier { matches: 2, percent: 30 }];
+
+    let mut stream = RandomnessStream::from_seed_and_nonce(8, 0);
+    for ticket in round.ticket_pool.sold_tickets(&round.pool_tickets) {
+        let mut numbers: Vec<u8> = (1..=8).collect();
+        let mut picks = stream.partial_shuffle(&mut numbers, 3).to_vec();
+        picks.sort();
+        let start = round.picks_offset(ticket);
+        round.pool_tickets[start..start + 3].copy_from_slice(&picks);
+    }
+    round
+}
+
+fn assert_fails(failures: &[String], expected: &str) {
+    assert!(
+        failures.iter().any(|failure| failure.contains(expected)),
+        "expected a failure containing {:?}, got {:?}", expected, failures
+    );
+}
+
+fn winners(round: &Round) -> Vec<u32> {
+    (1..=round.ticket_pool.capacity).filter(|&ticket| round.ticket_pool.is_winner(&round.pool_tickets, ticket)).collect()
+}
+
+#[test]
+fn honest_rounds_verify() {
+    for source in [RandomnessSource::SlotHash, RandomnessSource::CommitReveal, RandomnessSource::Oracle] {
+        let round = raffle(source.clone());
+        assert_eq!(round.lottery.winning_ticket_count, SOLD / 2);
+        assert_eq!(round.verify().failures, Vec::<String>::new(), "{:?}", source);
+    }
+}
+
+#[test]
+fn honest_pick_numbers_round_verifies() {
+    let round = pick_numbers();
+    assert!(!winners(&round).is_empty());
+    assert_eq!(winners(&round).len(), round.lottery.winning_ticket_count as usize);
+    assert_eq!(round.verify().failures, Vec::<String>::new());
+}
+
+#[test]
+fn rounds_drawn_in_several_batches_verify() {
+    let sold = 2 * DRAW_BATCH + 30;
+    let raffle = draw(round_of(RandomnessSource::Oracle, 0, sold + 10, sold));
+    assert_eq!(raffle.record.hashed_ticket_count, sold);
+    assert_eq!(raffle.lottery.winning_ticket_count, sold / 2);
+    assert_eq!(raffle.verify().failures, Vec::<String>::new());
+
+    let pick_numbers = draw(pick_numbers_round(round_of(RandomnessSource::SlotHash, 3, sold + 10, sold)));
+    assert_eq!(pick_numbers.lottery.tier_winners.iter().sum::<u32>(), pick_numbers.lottery.winning_ticket_count);
+    assert_eq!(pick_numbers.verify().failures, Vec::<String>::new());
+}
+
+#[test]
+fn running_draw_is_not_checked() {
+    let sold = 2 * DRAW_BATCH;
+    let mut round = round_of(RandomnessSource::SlotHash, 0, sold, sold);
+    round.lottery.start_draw(&round.ticket_pool, &mut round.pool_tickets, &mut round.record);
+    assert_eq!(round.lottery.status, LotteryStatus::Drawing);
+    let report = round.verify();
+    assert!(report.verified() && !report.drawn);
+}
+
+#[test]
+fn moved_winner_fails() {
+    let mut round = raffle(RandomnessSource::SlotHash);
+    let winner = winners(&round)[0];
+    let loser = round.ticket_pool.sold_tickets(&round.pool_tickets).into_iter()
+        .find(|&ticket| !round.ticket_pool.is_winner(&round.pool_tickets, ticket))
+        .unwrap();
+    round.set_winner(winner, false);
+    round.set_winner(loser, true);
+    assert_fails(&round.verify().failures, "ticket pool marks winning tickets");
+}
+
+#[test]
+fn other_seed_fails() {
+    // winners drawn from another slot hash than the recorded one
+    let mut round = round(RandomnessSource::SlotHash, 0);
+    round.record.randomness = [10u8; 32];
+    round.record.slot_hash = [10u8; 32];
+    let mut round = draw(round);
+    round.record.randomness = SLOT_HASH;
+    round.record.slot_hash = SLOT_HASH;
+    // undoing the shuffle with the recorded seed does not give back the hashed tickets
+    assert_fails(&round.verify().failures, "sold tickets hash does not match");
+
+    let mut pick_numbers = pick_numbers_round(self::round(RandomnessSource::SlotHash, 3));
+    pick_numbers.record.randomness = [10u8; 32];
+    pick_numbers.record.slot_hash = [10u8; 32];
+    let mut pick_numbers = draw(pick_numbers);
+    pick_numbers.record.randomness = SLOT_HASH;
+    pick_numbers.record.slot_hash = SLOT_HASH;
+    assert_fails(&pick_numbers.verify().failures, "draw record numbers");
+}
+
+#[test]
+fn report_holds_the_recomputed_draw() {
+    let round = pick_numbers();
+    let report = round.verify();
+    assert!(report.verified() && report.drawn);
+    assert_eq!(report.sold_tickets, SOLD as usize);
+    assert_eq!(report.winning_numbers, round.lottery.winning_numbers);
+    assert_eq!(report.tier_winners, round.lottery.tier_winners);
+    let mut winning_tickets = report.winning_tickets.clone();
+    winning_tickets.sort();
+    assert_eq!(winning_tickets, winners(&round));
+}
+
+#[test]
+fn ticket_pool_of_other_lottery_fails() {
+    let mut round = raffle(RandomnessSource::SlotHash);
+    round.ticket_pool.lottery = Pubkey::new_unique();
+    assert_fails(&round.verify().failures, "ticket pool belongs to lottery");
+}
+
+#[test]
+fn wrong_secret_fails() {
+    let mut round = raffle(RandomnessSource::CommitReveal);
+    round.record.randomness = [4u8; 32];
+    assert_fails(&round.verify().failures, "revealed secret does not hash to the commitment");
+}
+
+#[test]
+fn slot_hash_not_used_as_randomness_fails() {
+    let mut round = raffle(RandomnessSource::SlotHash);
+    round.record.slot_hash = [10u8; 32];
+    assert_fails(&round.verify().failures, "slot hash differs from the draw randomness");
+}
+
+#[test]
+fn changed_inputs_fail() {
+    let mut round = raffle(RandomnessSource::SlotHash);
+    round.lottery.entropy_accumulator = [6u8; 32];
+    assert_fails(&round.verify().failures, "entropy accumulator differs");
+
+    let mut round = raffle(RandomnessSource::SlotHash);
+    round.lottery.target_slot += 1;
+    assert_fails(&round.verify().failures, "target slot differs");
+
+    let mut round = raffle(RandomnessSource::SlotHash);
+    round.record.sold_tickets_hash = [0u8; 32];
+    assert_fails(&round.verify().failures, "sold tickets hash does not match");
+
+    let mut round = raffle(RandomnessSource::SlotHash);
+    round.lottery.tickets_sold -= 1;
+    assert_fails(&round.verify().failures, "sold ticket count");
+}
+
+#[test]
+fn changed_pick_numbers_outcome_fails() {
+    let mut round = pick_numbers();
+    round.lottery.tier_winners[0] += 1;
+    assert_fails(&round.verify().failures, "lottery tier winners");
+
+    let mut round = pick_numbers();
+    round.record.drawn_numbers.reverse();
+    assert_fails(&round.verify().failures, "draw record numbers");
+}
+
+#[test]
+fn winners_of_undrawn_round_fail() {
+    let mut round = round(RandomnessSource::SlotHash, 0);
+    round.lottery.status = LotteryStatus::Unresolved;
+    round.record.status = LotteryStatus::Unresolved;
+    assert_eq!(round.verify().failures, Vec::<String>::new());
+
+    round.lottery.winning_ticket_count = 1;
+    assert_fails(&round.verify().failures, "winners recorded for a round that was not drawn");
+}
+
+#[test]
+fn round_past_its_reveal_deadline_verifies_without_randomness() {
+    // the commit-reveal operator never revealed, the record holds no draw
+    let mut round = round(RandomnessSource::CommitReveal, 0);
+    round.lottery.reveal_deadline = 100;
+    round.lottery.status = LotteryStatus::Unresolved;
+    round.record.drawn_at = 101;
+    round.record.randomness = [0u8; 32];
+    round.record.slot_hash = [0u8; 32];
+    round.record.status = LotteryStatus::Unresolved;
+    let report = round.verify();
+    assert!(report.verified() && !report.drawn);
+
+    round.record.randomness = SECRET;
+    assert_fails(&round.verify().failures, "draw record past the reveal deadline holds a draw");
+}
+
+#[test]
+fn round_refunded_by_update_lottery_status_verifies() {
+    // the oracle never fulfilled the requested randomness, anyone moved the round to refunds
+    for source in [RandomnessSource::Oracle, RandomnessSource::SlotHash, RandomnessSource::CommitReveal] {
+        let mut round = round(source.clone(), 0);
+        round.lottery.randomness_account = Pubkey::new_unique();
+        round.lottery.reveal_deadline = 100;
+        round.lottery.status = LotteryStatus::Unresolved;
+        round.record.randomness_account = round.lottery.randomness_account;
+        round.record.drawn_at = 5_000;
+        round.record.randomness = [0u8; 32];
+        round.record.slot_hash = [0u8; 32];
+        round.record.status = LotteryStatus::Unresolved;
+        let report = round.verify();
+        assert_eq!(report.failures, Vec::<String>::new(), "{:?}", source);
+        assert!(!report.drawn);
+        assert!(report.randomness.starts_with("none"), "{:?}", source);
+    }
+}
+
+#[test]
+fn other_algorithm_version_fails() {
+    let mut round = raffle(RandomnessSource::SlotHash);
+    round.record.algorithm_version = DRAW_ALGORITHM_VERSION - 1;
+    let failures = round.verify().failures;
+    assert_eq!(failures.len(), 1);
+    assert_fails(&failures, "is not supported");
+}
+
+#[test]
+fn parses_ticket_pool_accounts() {
+    let round = raffle(RandomnessSource::SlotHash);
+    let mut data = TicketPool::DISCRIMINATOR.to_vec();
+    data.extend_from_slice(bytemuck::bytes_of(&round.ticket_pool));
+    data.extend_from_slice(&round.pool_tickets);
+
+    let (ticket_pool, pool_tickets) = parse_ticket_pool(&data).unwrap();
+    assert_eq!(ticket_pool.capacity, CAPACITY);
+    assert_eq!(ticket_pool.remaining, CAPACITY - SOLD);
+    assert_eq!(pool_tickets, round.pool_tickets);
+
+    assert_eq!(parse_ticket_pool(&data[..data.len() - 1]).err().unwrap(), "ticket pool is truncated");
+    assert_eq!(parse_ticket_pool(&data[..8]).err().unwrap(), "not a ticket pool account");
+    data[0] ^= 1;
+    assert_eq!(parse_ticket_pool(&data).err().unwrap(), "not a ticket pool account");
+}